@@ -1,7 +1,7 @@
 use crate::{
     flycam::FlycamPlugin,
     orbit_camera::OrbitCameraPlugin,
-    physics::{CollisionLayer, PhysicsPlugin},
+    physics::{CharacterTimestep, CollisionLayer, KinematicCharacterBody, PhysicsPlugin},
     player::{types::Player, PlayerPlugin, SpawnPlayer},
 };
 use avian3d::prelude::*;
@@ -34,7 +34,7 @@ impl Plugin for GamePlugin {
             PlayerPlugin,
             OrbitCameraPlugin,
             FlycamPlugin,
-            PhysicsPlugin::default(),
+            PhysicsPlugin::default().with_timestep(CharacterTimestep::Fixed),
         ));
 
         app.add_systems(Startup, setup);
@@ -80,6 +80,7 @@ fn setup(mut commands: Commands, asset_server: ResMut<AssetServer>) {
 
     commands.trigger(SpawnPlayer {
         transform: Transform::from_translation(Vec3::new(0.0, 3.0, 0.0)),
+        body: KinematicCharacterBody::default().with_push_dynamic_bodies(true),
     });
 }
 
@@ -104,6 +105,7 @@ fn spawn_spheres(
             MeshMaterial3d(material),
             RigidBody::Dynamic,
             Collider::sphere(0.5),
+            CollisionLayers::new(CollisionLayer::Prop, LayerMask::ALL),
             *player_transform,
             ExternalImpulse::new(player_transform.forward().as_vec3() * 10.0),
         ));
@@ -5,6 +5,8 @@ mod game;
 mod orbit_camera;
 mod physics;
 mod player;
+#[cfg(test)]
+mod test_harness;
 
 use bevy::prelude::*;
 use game::GamePlugin;
@@ -50,12 +50,15 @@ impl Default for Smoothing {
 #[derive(Component)]
 pub struct PreventBlindness {
     camera_collider: Collider,
+    /// colliders that can block the view onto the target, the target itself is always ignored
+    pub filter: SpatialQueryFilter,
 }
 
 impl Default for PreventBlindness {
     fn default() -> Self {
         Self {
             camera_collider: Collider::sphere(0.25),
+            filter: SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
         }
     }
 }
@@ -114,10 +117,10 @@ fn follow_target(
 
 fn prevent_blindness(
     mut cameras: Query<(&mut Transform, &PreventBlindness), With<OrbitCamera>>,
-    targets: Query<(&Transform, &TargetOf), Without<OrbitCamera>>,
+    targets: Query<(Entity, &Transform, &TargetOf), Without<OrbitCamera>>,
     spatial_query: SpatialQuery,
 ) {
-    for (target_transform, target_of) in &targets {
+    for (target, target_transform, target_of) in &targets {
         let (mut camera_transform, pb) = cameras.get_mut(target_of.0).unwrap();
         let direction = camera_transform.translation - target_transform.translation;
         let distance = direction.length();
@@ -137,7 +140,7 @@ fn prevent_blindness(
                 max_distance: distance,
                 ..Default::default()
             },
-            &SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
+            &pb.filter.clone().with_excluded_entities([target]),
         ) {
             let hit_position = target_transform.translation + direction * hit.distance;
             camera_transform.translation = hit_position;
@@ -1,54 +1,127 @@
-use avian3d::prelude::*;
-use bevy::{color::palettes::tailwind, prelude::*};
+use avian3d::{
+    parry::{na, shape::ShapeType},
+    prelude::*,
+};
+use bevy::{
+    color::palettes::tailwind,
+    ecs::{entity::EntityHashSet, schedule::ScheduleLabel, system::SystemParam},
+    gltf::GltfMaterialName,
+    platform::collections::HashMap,
+    prelude::*,
+};
+use std::sync::Arc;
 // TODO: apply small offset to avoid extended collider from penetrating surfaces
 
 // small number used to work around floating point inaccuracies
 const EPSILON: f32 = 1e-04;
+// minimum distance in front of the body that has to be free while stepping onto obstacles, so
+// obstacles hit right at the end of the body's movement can still be stepped onto
+const MIN_STEP_PROBE_DISTANCE: f32 = 0.01;
 
 pub struct PhysicsPlugin {
     collide_and_slide_max_iterations: usize,
+    depenetration_max_iterations: usize,
+    debug_visualization: bool,
+    timestep: CharacterTimestep,
+}
+
+impl PhysicsPlugin {
+    pub fn with_timestep(mut self, timestep: CharacterTimestep) -> Self {
+        self.timestep = timestep;
+        self
+    }
+
+    pub fn with_debug_visualization(mut self, debug_visualization: bool) -> Self {
+        self.debug_visualization = debug_visualization;
+        self
+    }
 }
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        // let collider = Collider::capsule(1.0, 1.0);
-        // println!(
-        //     "{:?}",
-        //     distance_from_center_to_hull(&collider, Quat::IDENTITY, Dir3::new_unchecked(Vec3::Y))
-        // );
-        // let collider = inflated_collider(&collider, -0.5);
-        // println!(
-        //     "{:?}",
-        //     distance_from_center_to_hull(&collider, Quat::IDENTITY, Dir3::new_unchecked(Vec3::Y))
-        // );
-
-        app.add_plugins((
-            // PhysicsPlugins::default().set(PhysicsInterpolationPlugin::extrapolate_all()),
-            PhysicsPlugins::default(),
-            // PhysicsDebugPlugin::default(),
-        ));
+        match self.timestep {
+            CharacterTimestep::Variable => {
+                app.add_plugins((
+                    // PhysicsPlugins::default().set(PhysicsInterpolationPlugin::extrapolate_all()),
+                    PhysicsPlugins::default(),
+                    // PhysicsDebugPlugin::default(),
+                ));
+                app.add_systems(
+                    PostUpdate,
+                    run_character_schedule.before(TransformSystem::TransformPropagate),
+                );
+            }
+            CharacterTimestep::Fixed => {
+                // bodies are moved by changing their transform in `FixedUpdate`, which the
+                // interpolation picks up just like the movement of rigid bodies
+                app.add_plugins(
+                    PhysicsPlugins::default().set(PhysicsInterpolationPlugin::interpolate_all()),
+                );
+                app.add_systems(FixedUpdate, run_character_schedule);
+            }
+        }
 
         app.insert_resource(CollideAndSlideMaxIterations(
             self.collide_and_slide_max_iterations,
         ));
+        app.insert_resource(DepenetrationMaxIterations(
+            self.depenetration_max_iterations,
+        ));
+        app.init_resource::<SurfaceMaterialNames>()
+            .init_resource::<WaterMaterialNames>()
+            .add_systems(
+                Update,
+                (
+                    assign_surface_materials,
+                    assign_water_volumes,
+                    configure_sensor_volumes.after(assign_water_volumes),
+                ),
+            )
+            .add_systems(
+                FixedPostUpdate,
+                float_dynamic_bodies.before(PhysicsSet::StepSimulation),
+            );
+        app.add_event::<DepenetrationFailed>()
+            .add_event::<SlidingStarted>()
+            .add_event::<SlidingStopped>()
+            .add_event::<HitCeiling>();
+
+        app.configure_sets(
+            CharacterSchedule,
+            (CharacterSet::Movement, CharacterSet::Physics).chain(),
+        );
+
+        app.add_systems(
+            CharacterSchedule,
+            detect_water.before(CharacterSet::Movement),
+        );
 
         app.add_systems(
-            PostUpdate,
+            CharacterSchedule,
             (
+                align_with_up,
+                move_with_ground,
+                depenetrate,
                 collide_and_slide,
+                respond_to_ceiling,
+                push_dynamic_bodies,
                 snap_to_ground,
-                collide_and_slide_debug_visualization,
                 respond_to_ground,
+                update_drop_through,
             )
-                .chain(),
+                .chain()
+                .in_set(CharacterSet::Physics),
         );
 
-        // app.add_systems(
-        //     PhysicsSchedule,
-        //     (collide_and_slide, respond_to_ground)
-        //         .chain()
-        //         .in_set(PhysicsStepSet::First),
-        // );
+        if self.debug_visualization {
+            app.add_systems(
+                CharacterSchedule,
+                collide_and_slide_debug_visualization
+                    .after(snap_to_ground)
+                    .before(respond_to_ground)
+                    .in_set(CharacterSet::Physics),
+            );
+        }
     }
 }
 
@@ -56,40 +129,249 @@ impl Default for PhysicsPlugin {
     fn default() -> Self {
         Self {
             collide_and_slide_max_iterations: 8,
+            depenetration_max_iterations: 4,
+            debug_visualization: true,
+            timestep: CharacterTimestep::default(),
         }
     }
 }
 
+/// Determines in which schedule and with which delta time [`CharacterSchedule`] runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CharacterTimestep {
+    /// once per frame in [`PostUpdate`], using the frame's delta time
+    #[default]
+    Variable,
+    /// in [`FixedUpdate`], using the fixed delta time. Transforms are interpolated between fixed
+    /// steps, so rendering stays smooth at any refresh rate.
+    Fixed,
+}
+
+/// Schedule that moves character bodies. Gameplay systems that change the [`Velocity`] of bodies
+/// should run in [`CharacterSet::Movement`], to share the timestep of the character pipeline.
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CharacterSchedule;
+
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CharacterSet {
+    /// updates velocities of bodies
+    Movement,
+    /// moves bodies according to their velocities and resolves collisions
+    Physics,
+}
+
+fn run_character_schedule(world: &mut World) {
+    world.run_schedule(CharacterSchedule);
+}
+
 #[derive(PhysicsLayer, Default)]
 pub enum CollisionLayer {
     #[default]
     Default,
     Player,
     Terrain,
+    /// dynamic objects like crates or balls
+    Prop,
+    /// characters that aren't controlled by the player
+    Character,
+    /// sensors that detect bodies entering them
+    Trigger,
+    Water,
 }
 
 #[derive(Resource)]
 pub struct CollideAndSlideMaxIterations(usize);
 
+#[derive(Resource)]
+pub struct DepenetrationMaxIterations(usize);
+
+/// Sent when a body is still overlapping other colliders after [`DepenetrationMaxIterations`]
+/// attempts to push it out of them.
+#[derive(Event, Debug)]
+pub struct DepenetrationFailed {
+    pub entity: Entity,
+    /// depth of the deepest remaining penetration
+    pub penetration: f32,
+}
+
 #[derive(Component)]
-#[require(Velocity, Transform, RigidBody::Kinematic, Collider)]
+#[require(
+    Velocity,
+    GroundVelocity,
+    CharacterCollisions,
+    Transform,
+    RigidBody::Kinematic,
+    Collider
+)]
 pub struct KinematicCharacterBody {
+    /// direction the body considers to be up. It is used for slope angles, snapping, gravity and
+    /// the orientation of the collider, so it can be changed to walk on walls, ceilings or planets.
+    pub up: Dir3,
     /// maximum distance between collider and ground for the body to be considered grounded
     grounded_max_distance: f32,
     /// body will slide off of terrain with slope angles greater than ['max_terrain_slope']
     max_terrain_slope: f32,
+    /// surfaces hit with normals deviating less than this angle from the down direction are treated
+    /// as ceilings, which stop upward movement
+    max_ceiling_slope: f32,
     snap_to_ground: bool,
     /// maximum distance to floor, at which snapping can occur
     snap_to_ground_max_distance: f32,
+    /// maximum height of obstacles like stairs or curbs, that the body will step onto instead of
+    /// treating them as walls
+    max_step_height: f32,
+    /// whether the body collides with dynamic rigid bodies and pushes them away. Otherwise it passes
+    /// through dynamic rigid bodies, even if they are part of the [`CharacterFilter::mask`].
+    push_dynamic_bodies: bool,
+    /// mass of the body in kilograms, used to determine how hard it pushes dynamic rigid bodies
+    mass: f32,
+    /// maximum impulse applied to a single dynamic rigid body per update, summed over all contacts
+    /// with it
+    max_push_impulse: f32,
+    /// how the [`Velocity`] of the body changes when [`collide_and_slide`] hits something
+    velocity_response: VelocityResponse,
+    /// determines which colliders the body collides with
+    pub collision_filter: CharacterFilter,
 }
 
 impl Default for KinematicCharacterBody {
     fn default() -> Self {
         Self {
+            up: Dir3::Y,
             grounded_max_distance: 0.05,
             max_terrain_slope: 45f32.to_radians(),
+            max_ceiling_slope: 45f32.to_radians(),
             snap_to_ground: true,
             snap_to_ground_max_distance: 0.5,
+            max_step_height: 0.25,
+            push_dynamic_bodies: false,
+            mass: 80.0,
+            max_push_impulse: 50.0,
+            velocity_response: VelocityResponse::Keep,
+            collision_filter: CharacterFilter::default(),
+        }
+    }
+}
+
+impl KinematicCharacterBody {
+    pub fn with_push_dynamic_bodies(mut self, push_dynamic_bodies: bool) -> Self {
+        self.push_dynamic_bodies = push_dynamic_bodies;
+        self
+    }
+
+    /// Filter for the spatial queries the body with the given entity uses to find terrain and other
+    /// obstacles. Has to be combined with [`KinematicCharacterBody::collides_with`] as predicate.
+    pub fn filter(&self, entity: Entity) -> SpatialQueryFilter {
+        SpatialQueryFilter::from_mask(self.collision_filter.mask).with_excluded_entities(
+            self.collision_filter
+                .excluded_entities
+                .iter()
+                .copied()
+                .chain([entity]),
+        )
+    }
+
+    /// Whether the body collides with `collider`, according to [`CharacterFilter::predicate`].
+    /// Dynamic rigid bodies are only collided with, if the body pushes them.
+    pub fn collides_with(&self, collider: Entity, dynamic_bodies: &DynamicBodies) -> bool {
+        (self.push_dynamic_bodies || !dynamic_bodies.contains(collider))
+            && self
+                .collision_filter
+                .predicate
+                .as_ref()
+                .is_none_or(|predicate| predicate(collider))
+    }
+
+    /// Whether the body can stand on surfaces with the given normal.
+    pub fn is_walkable(&self, normal: Vec3) -> bool {
+        normal.angle_between(*self.up) <= self.max_terrain_slope
+    }
+}
+
+/// Determines which colliders a [`KinematicCharacterBody`] collides with. The body's own collider is
+/// always ignored.
+#[derive(Clone)]
+pub struct CharacterFilter {
+    /// layers of the colliders the body collides with
+    pub mask: LayerMask,
+    /// colliders the body ignores, e.g. its own sensors
+    pub excluded_entities: EntityHashSet,
+    /// called for colliders matching the mask, the body passes through colliders it returns
+    /// `false` for
+    pub predicate: Option<Arc<dyn Fn(Entity) -> bool + Send + Sync>>,
+}
+
+impl CharacterFilter {
+    pub fn from_mask(mask: impl Into<LayerMask>) -> Self {
+        Self {
+            mask: mask.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_excluded_entities(mut self, entities: impl IntoIterator<Item = Entity>) -> Self {
+        self.excluded_entities.extend(entities);
+        self
+    }
+
+    pub fn with_predicate(
+        mut self,
+        predicate: impl Fn(Entity) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+}
+
+impl Default for CharacterFilter {
+    fn default() -> Self {
+        Self {
+            mask: [
+                CollisionLayer::Default,
+                CollisionLayer::Terrain,
+                CollisionLayer::Prop,
+            ]
+            .into(),
+            excluded_entities: EntityHashSet::default(),
+            predicate: None,
+        }
+    }
+}
+
+/// Determines how [`collide_and_slide`] writes the result of sliding along surfaces back into the
+/// [`Velocity`] of a body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityResponse {
+    /// velocity is left unchanged, only the movement slides along surfaces
+    Keep,
+    /// velocity into hit surfaces is removed, so the body keeps sliding along them
+    Clip,
+    /// velocity into hit surfaces is reflected and scaled by the given restitution
+    Bounce(f32),
+}
+
+impl VelocityResponse {
+    fn respond(self, velocity: Vec3, normal: Vec3, previous_normal: Option<Vec3>) -> Vec3 {
+        let restitution = match self {
+            Self::Keep => return velocity,
+            Self::Clip => 0.0,
+            Self::Bounce(restitution) => restitution,
+        };
+
+        let normal_speed = velocity.dot(normal);
+        if normal_speed >= 0.0 {
+            return velocity;
+        }
+        let velocity = velocity - normal * normal_speed * (1.0 + restitution);
+
+        // in corners, clipping against one surface can point the velocity into the previously hit
+        // one, in which case only movement along the crease between both is left
+        match previous_normal {
+            Some(previous_normal) if restitution == 0.0 && velocity.dot(previous_normal) < 0.0 => {
+                let crease = previous_normal.cross(normal).normalize_or_zero();
+                crease * velocity.dot(crease)
+            }
+            _ => velocity,
         }
     }
 }
@@ -111,51 +393,450 @@ impl std::ops::DerefMut for Velocity {
     }
 }
 
+/// Contacts [`collide_and_slide`] found while moving the body during the last update.
+#[derive(Debug, Default, Component)]
+pub struct CharacterCollisions(pub Vec<CharacterCollision>);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CharacterCollision {
+    /// the collider that was hit
+    pub entity: Entity,
+    /// contact point in world space
+    pub point: Vec3,
+    /// surface normal of the hit collider at the contact point, pointing towards the body
+    pub normal: Vec3,
+    /// distance the body moved during the slide iteration before making contact
+    pub distance: f32,
+    /// velocity of the body right before making contact
+    pub velocity: Vec3,
+    /// slide iteration the contact was found in, starting at 0
+    pub iteration: usize,
+}
+
 #[derive(Component)]
 pub struct Grounded;
 
+/// Sent when [`collide_and_slide`] moves a body into a ceiling.
+#[derive(Event, Debug)]
+pub struct HitCeiling {
+    pub entity: Entity,
+    /// the collider that was hit
+    pub ceiling: Entity,
+    pub normal: Vec3,
+}
+
+/// Marks bodies that rest on terrain steeper than [`KinematicCharacterBody::max_terrain_slope`] and
+/// slide down it instead of being [`Grounded`]. Moving the body down the slope is left to its
+/// controller, e.g. through gravity, which [`collide_and_slide`] turns into sliding.
+#[derive(Debug, Component)]
+pub struct Sliding {
+    /// normal of the slope the body slides down on
+    pub normal: Vec3,
+}
+
+#[derive(Event, Debug)]
+pub struct SlidingStarted {
+    pub entity: Entity,
+}
+
+#[derive(Event, Debug)]
+pub struct SlidingStopped {
+    pub entity: Entity,
+}
+
+/// Information about the ground a [`Grounded`] body is standing on.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct GroundInfo {
+    /// the rigid body the ground belongs to
+    pub entity: Entity,
+    /// surface normal of the ground below the body
+    pub normal: Vec3,
+    /// angle between the ground normal and [`KinematicCharacterBody::up`] in radians
+    pub slope_angle: f32,
+    /// distance between the collider of the body and the ground
+    pub distance: f32,
+    /// elapsed [`Time`] in seconds, at which the body landed on the ground
+    pub landed_at: f32,
+    /// surface of the ground, the default surface if the ground has no [`SurfaceMaterial`]
+    pub surface: SurfaceMaterial,
+}
+
+/// The ground a body stood on before leaving it, e.g. by jumping or walking off of a ledge. Removed
+/// once the body lands again.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct LastGround {
+    pub info: GroundInfo,
+    /// elapsed [`Time`] in seconds, at which the body left the ground
+    pub left_at: f32,
+}
+
+/// Changes how bodies move on terrain, e.g. to make ice slippery or mud slow. All values are
+/// factors, which scale the corresponding movement values of the body. Can be added to the collider
+/// or to its rigid body.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct SurfaceMaterial {
+    pub acceleration: f32,
+    /// scales how fast bodies slow down without input
+    pub friction: f32,
+    pub max_speed: f32,
+}
+
+impl SurfaceMaterial {
+    pub const ICE: Self = Self {
+        acceleration: 0.2,
+        friction: 0.05,
+        max_speed: 1.0,
+    };
+    pub const MUD: Self = Self {
+        acceleration: 0.6,
+        friction: 2.0,
+        max_speed: 0.5,
+    };
+    pub const SAND: Self = Self {
+        acceleration: 0.8,
+        friction: 1.5,
+        max_speed: 0.7,
+    };
+    pub const SPEED_BOOST: Self = Self {
+        acceleration: 2.0,
+        friction: 1.0,
+        max_speed: 1.6,
+    };
+}
+
+impl Default for SurfaceMaterial {
+    fn default() -> Self {
+        Self {
+            acceleration: 1.0,
+            friction: 1.0,
+            max_speed: 1.0,
+        }
+    }
+}
+
+/// Maps names of glTF materials to the [`SurfaceMaterial`] that is added to meshes using them, e.g.
+/// to terrain colliders created by a [`ColliderConstructorHierarchy`].
+#[derive(Debug, Default, Resource)]
+pub struct SurfaceMaterialNames(pub HashMap<String, SurfaceMaterial>);
+
+/// Velocity of the ground at the position of the body. It is added to the body's [`Velocity`] once
+/// the body leaves the ground, so momentum is kept when jumping off of moving platforms.
+#[derive(Debug, Default, Component)]
+pub struct GroundVelocity(pub Vec3);
+
+/// Platform that bodies only collide with while moving against [`OneWayPlatform::up`], so they can
+/// jump up through it from below and land on top of it. Can be added to the collider or to its
+/// rigid body.
+#[derive(Debug, Component)]
+pub struct OneWayPlatform {
+    /// direction bodies can pass through the platform in
+    pub up: Dir3,
+}
+
+impl Default for OneWayPlatform {
+    fn default() -> Self {
+        Self { up: Dir3::Y }
+    }
+}
+
+/// Makes a body fall through [`OneWayPlatform`]s, e.g. after the player asked to drop down from one.
+/// Removed automatically once the time is up.
+#[derive(Debug, Component)]
+pub struct DropThrough {
+    /// seconds left until the body collides with one-way platforms again
+    pub remaining: f32,
+}
+
+impl Default for DropThrough {
+    fn default() -> Self {
+        Self { remaining: 0.25 }
+    }
+}
+
+/// Body of water, approximated by the bounding box of its collider. Its surface is the side of the
+/// box facing up, which is [`KinematicCharacterBody::up`] for character bodies and the opposite of
+/// [`Gravity`] for rigid bodies. Character bodies overlapping it are marked as [`Submerged`] and
+/// dynamic rigid bodies float in it.
+/// Its collider is turned into a sensor on the [`CollisionLayer::Water`] layer.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct WaterVolume {
+    /// upward acceleration on completely submerged bodies, as a multiple of gravity
+    pub buoyancy: f32,
+    /// fraction of their velocity submerged bodies lose per second
+    pub drag: f32,
+}
+
+impl Default for WaterVolume {
+    fn default() -> Self {
+        Self {
+            buoyancy: 1.5,
+            drag: 2.0,
+        }
+    }
+}
+
+/// Maps names of glTF materials to the [`WaterVolume`] that is added to meshes using them.
+#[derive(Debug, Default, Resource)]
+pub struct WaterMaterialNames(pub HashMap<String, WaterVolume>);
+
+/// Added to character bodies overlapping a [`WaterVolume`].
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Submerged {
+    /// the collider of the water volume
+    pub volume: Entity,
+    pub water: WaterVolume,
+    /// height of the water surface along [`KinematicCharacterBody::up`]
+    pub surface_height: f32,
+    /// fraction of the body's height below the surface, from 0 to 1
+    pub fraction: f32,
+}
+
+/// Volume in front of a ladder or a climbable wall, in which characters can climb. Its collider is
+/// turned into a sensor on the [`CollisionLayer::Trigger`] layer.
+#[derive(Debug, Component)]
+pub struct Climbable {
+    /// direction pointing away from the climbable surface, in the local space of the volume
+    pub normal: Dir3,
+}
+
+impl Default for Climbable {
+    fn default() -> Self {
+        Self { normal: Dir3::Z }
+    }
+}
+
+/// Keeps a body from being grounded, sliding or snapping to the ground, e.g. while it climbs.
+#[derive(Debug, Component)]
+pub struct IgnoreGround;
+
+/// Looks up whether a collider belongs to a dynamic rigid body.
+#[derive(SystemParam)]
+pub struct DynamicBodies<'w, 's> {
+    colliders: Query<'w, 's, &'static ColliderOf>,
+    rigid_bodies: Query<'w, 's, &'static RigidBody>,
+}
+
+impl DynamicBodies<'_, '_> {
+    pub fn contains(&self, collider: Entity) -> bool {
+        let body = self
+            .colliders
+            .get(collider)
+            .map_or(collider, |collider_of| collider_of.body);
+        self.rigid_bodies
+            .get(body)
+            .is_ok_and(|rigid_body| rigid_body.is_dynamic())
+    }
+}
+
+/// Looks up the [`OneWayPlatform`] a collider belongs to.
+#[derive(SystemParam)]
+pub struct OneWayPlatforms<'w, 's> {
+    colliders: Query<'w, 's, &'static ColliderOf>,
+    platforms: Query<'w, 's, &'static OneWayPlatform>,
+}
+
+impl OneWayPlatforms<'_, '_> {
+    pub fn get(&self, collider: Entity) -> Option<&OneWayPlatform> {
+        let body = self
+            .colliders
+            .get(collider)
+            .map_or(collider, |collider_of| collider_of.body);
+        self.platforms
+            .get(collider)
+            .or_else(|_| self.platforms.get(body))
+            .ok()
+    }
+
+    /// Colliders of the one-way platforms `collider` overlaps. Bodies are in the middle of passing
+    /// through these and keep ignoring them until they left them.
+    fn overlapping(
+        &self,
+        spatial_query: &SpatialQuery,
+        collider: &Collider,
+        position: Vec3,
+        rotation: Quat,
+        filter: &SpatialQueryFilter,
+    ) -> Vec<Entity> {
+        if self.platforms.is_empty() {
+            return Vec::new();
+        }
+        spatial_query
+            .shape_intersections(collider, position, rotation, filter)
+            .into_iter()
+            .filter(|entity| self.get(*entity).is_some())
+            .collect()
+    }
+
+    /// Whether `collider` stops a body moving in `direction`. Colliders that don't belong to a
+    /// one-way platform always do. `passing` are the platforms the body overlaps and `dropping`
+    /// whether it has [`DropThrough`].
+    fn blocks(
+        &self,
+        collider: Entity,
+        direction: Vec3,
+        passing: &[Entity],
+        dropping: bool,
+    ) -> bool {
+        let Some(platform) = self.get(collider) else {
+            return true;
+        };
+        !dropping && !passing.contains(&collider) && direction.dot(*platform.up) <= 0.0
+    }
+}
+
+/// Rotates bodies, so their local y axis points in the direction of
+/// [`KinematicCharacterBody::up`].
+fn align_with_up(mut bodies: Query<(&KinematicCharacterBody, &mut Transform)>) {
+    for (body, mut transform) in &mut bodies {
+        let local_up = transform.up();
+        if local_up.dot(*body.up) < 1.0 - EPSILON {
+            transform.rotation = Quat::from_rotation_arc(*local_up, *body.up) * transform.rotation;
+        }
+    }
+}
+
+/// Carries bodies along with the kinematic or dynamic rigid body they are standing on.
+fn move_with_ground(
+    mut bodies: Query<(&KinematicCharacterBody, &mut Transform, &GroundInfo), With<Grounded>>,
+    grounds: Query<(
+        &LinearVelocity,
+        &AngularVelocity,
+        &Position,
+        &Rotation,
+        &ComputedCenterOfMass,
+    )>,
+    time: Res<Time>,
+) {
+    for (body, mut transform, ground) in &mut bodies {
+        let Ok((linear_velocity, angular_velocity, position, rotation, center_of_mass)) =
+            grounds.get(ground.entity)
+        else {
+            continue;
+        };
+
+        let center_of_mass = world_center_of_mass(position, rotation, center_of_mass);
+        let delta_rotation = Quat::from_scaled_axis(angular_velocity.0 * time.delta_secs());
+        transform.translation = center_of_mass
+            + delta_rotation * (transform.translation - center_of_mass)
+            + linear_velocity.0 * time.delta_secs();
+        // only inherit yaw, so the body stays upright on tilting platforms
+        let yaw = angular_velocity.dot(*body.up) * time.delta_secs();
+        transform.rotate(Quat::from_axis_angle(*body.up, yaw));
+    }
+}
+
 pub fn collide_and_slide(
     mut bodies: Query<(
+        Entity,
         &KinematicCharacterBody,
         &Collider,
-        &Velocity,
+        &mut Velocity,
         &mut Transform,
+        &mut CharacterCollisions,
+        Has<Grounded>,
+        Has<DropThrough>,
     )>,
     spatial_query: SpatialQuery,
+    one_way_platforms: OneWayPlatforms,
+    dynamic_bodies: DynamicBodies,
     max_iterations: Res<CollideAndSlideMaxIterations>,
     time: Res<Time>,
 ) {
-    bodies
-        .par_iter_mut()
-        .for_each(|(_body, collider, velocity, mut transform)| {
+    bodies.par_iter_mut().for_each(
+        |(
+            entity,
+            body,
+            collider,
+            mut velocity,
+            mut transform,
+            mut collisions,
+            is_grounded,
+            is_dropping,
+        )| {
+            collisions.0.clear();
+            let Some(adjusted_collider) = adjusted_collider(collider) else {
+                return;
+            };
+            let filter = body.filter(entity);
+            let passing = one_way_platforms.overlapping(
+                &spatial_query,
+                &adjusted_collider,
+                transform.translation,
+                transform.rotation,
+                &filter,
+            );
+            let blocks = |collider: Entity, direction: Vec3| {
+                body.collides_with(collider, &dynamic_bodies)
+                    && one_way_platforms.blocks(collider, direction, &passing, is_dropping)
+            };
             let mut remaining_velocity = velocity.0 * time.delta_secs();
             let mut remaining_distance = remaining_velocity.length();
-            let adjusted_collider = inflated_collider(collider, -EPSILON);
             let mut position = transform.translation;
             let mut direction = remaining_velocity.normalize();
+            let mut slide_velocity = velocity.0;
+            let mut previous_normal = None;
             let mut i = 0;
             while i < max_iterations.0 && remaining_distance > 0.0 {
-                if let Some(hit) = spatial_query.cast_shape(
+                if let Some(hit) = spatial_query.cast_shape_predicate(
                     &adjusted_collider,
                     position,
-                    Quat::IDENTITY,
+                    transform.rotation,
                     Dir3::new_unchecked(direction),
                     &ShapeCastConfig {
                         max_distance: remaining_distance + EPSILON,
                         ..Default::default()
                     },
-                    &SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
+                    &filter,
+                    &|collider| blocks(collider, direction),
                 ) {
+                    collisions.0.push(CharacterCollision {
+                        entity: hit.entity,
+                        point: hit.point1,
+                        normal: hit.normal1,
+                        distance: hit.distance,
+                        velocity: slide_velocity,
+                        iteration: i,
+                    });
+
                     let mut new_position = position + direction * hit.distance;
                     new_position += hit.normal1 * EPSILON;
 
                     remaining_distance -= position.distance(new_position);
                     remaining_velocity =
                         remaining_velocity.normalize_or_zero() * remaining_distance;
+                    position = new_position;
+
+                    // only step onto obstacles while walking, stepping mid-air would let the body
+                    // climb walls by jumping against them
+                    if is_grounded && !body.is_walkable(hit.normal1) {
+                        if let Some((stepped_position, stepped_remaining_velocity)) = step_up(
+                            &spatial_query,
+                            &adjusted_collider,
+                            body,
+                            &filter,
+                            &blocks,
+                            position,
+                            transform.rotation,
+                            remaining_velocity,
+                        ) {
+                            position = stepped_position;
+                            remaining_velocity = stepped_remaining_velocity;
+                            remaining_distance = remaining_velocity.length();
+                            direction = remaining_velocity.normalize();
+                            i += 1;
+                            continue;
+                        }
+                    }
+
                     remaining_velocity = remaining_velocity.reject_from_normalized(hit.normal1);
                     direction = remaining_velocity.normalize();
 
-                    position = new_position;
+                    slide_velocity = body.velocity_response.respond(
+                        slide_velocity,
+                        hit.normal1,
+                        previous_normal,
+                    );
+                    previous_normal = Some(hit.normal1);
                 } else {
                     position += remaining_velocity;
                     break;
@@ -165,48 +846,390 @@ pub fn collide_and_slide(
             }
 
             transform.translation = position;
+            if body.velocity_response != VelocityResponse::Keep {
+                velocity.0 = slide_velocity;
+            }
+        },
+    );
+}
+
+/// Pushes bodies out of colliders they overlap with, e.g. after spawning inside of geometry or
+/// being moved into it by a kinematic body. The deepest penetration is resolved first, by moving the
+/// body along the minimum translation vector. Overlapping [`OneWayPlatform`]s are left alone, as
+/// bodies overlap them while passing through, and so are dynamic rigid bodies, which the physics
+/// solver already separates from the body.
+fn depenetrate(
+    mut bodies: Query<(Entity, &KinematicCharacterBody, &Collider, &mut Transform)>,
+    colliders: Query<(&Collider, &Position, &Rotation)>,
+    dynamic_bodies: DynamicBodies,
+    spatial_query: SpatialQuery,
+    one_way_platforms: OneWayPlatforms,
+    max_iterations: Res<DepenetrationMaxIterations>,
+    mut depenetration_failed: EventWriter<DepenetrationFailed>,
+) {
+    for (entity, body, collider, mut transform) in &mut bodies {
+        let Some(adjusted_collider) = adjusted_collider(collider) else {
+            continue;
+        };
+        let filter = body.filter(entity);
+
+        let mut i = 0;
+        while let Some((direction, penetration)) = deepest_penetration(
+            &spatial_query,
+            &colliders,
+            &adjusted_collider,
+            transform.translation,
+            transform.rotation,
+            &filter,
+            &|collider| {
+                body.collides_with(collider, &dynamic_bodies)
+                    && one_way_platforms.get(collider).is_none()
+                    && !dynamic_bodies.contains(collider)
+            },
+        ) {
+            if i >= max_iterations.0 {
+                depenetration_failed.write(DepenetrationFailed {
+                    entity,
+                    penetration,
+                });
+                break;
+            }
+
+            transform.translation += direction * (penetration + EPSILON);
+            i += 1;
+        }
+    }
+}
+
+/// Returns the direction and depth of the deepest penetration between `collider` and the colliders
+/// matching `filter` and `predicate`.
+fn deepest_penetration(
+    spatial_query: &SpatialQuery,
+    colliders: &Query<(&Collider, &Position, &Rotation)>,
+    collider: &Collider,
+    position: Vec3,
+    rotation: Quat,
+    filter: &SpatialQueryFilter,
+    predicate: &dyn Fn(Entity) -> bool,
+) -> Option<(Vec3, f32)> {
+    let body_isometry = isometry(position, rotation);
+    spatial_query
+        .shape_intersections(collider, position, rotation, filter)
+        .into_iter()
+        .filter(|entity| predicate(*entity))
+        .filter_map(|entity| {
+            let (other_collider, other_position, other_rotation) = colliders.get(entity).ok()?;
+            let contact = avian3d::parry::query::contact(
+                &body_isometry,
+                &**collider.shape(),
+                &isometry(other_position.0, other_rotation.0),
+                &**other_collider.shape_scaled(),
+                0.0,
+            )
+            .ok()??;
+            // the second normal points out of the other collider, towards the body
+            let normal = contact.normal2;
+            (contact.dist < 0.0).then(|| (Vec3::new(normal.x, normal.y, normal.z), -contact.dist))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+fn isometry(translation: Vec3, rotation: Quat) -> na::Isometry3<f32> {
+    na::Isometry3::from_parts(
+        na::Translation3::new(translation.x, translation.y, translation.z),
+        na::UnitQuaternion::new_normalize(na::Quaternion::new(
+            rotation.w, rotation.x, rotation.y, rotation.z,
+        )),
+    )
+}
+
+/// Cancels the upward velocity of bodies that hit a ceiling during [`collide_and_slide`], so they
+/// don't stick to it until gravity has used up their upward velocity.
+fn respond_to_ceiling(
+    mut bodies: Query<(
+        Entity,
+        &KinematicCharacterBody,
+        &CharacterCollisions,
+        &mut Velocity,
+    )>,
+    mut hit_ceiling: EventWriter<HitCeiling>,
+) {
+    for (entity, body, collisions, mut velocity) in &mut bodies {
+        let Some(ceiling) = collisions
+            .0
+            .iter()
+            .find(|collision| collision.normal.angle_between(-body.up) <= body.max_ceiling_slope)
+        else {
+            continue;
+        };
+
+        let upward_speed = velocity.dot(*body.up);
+        if upward_speed > 0.0 {
+            velocity.0 -= body.up * upward_speed;
+        }
+        hit_ceiling.write(HitCeiling {
+            entity,
+            ceiling: ceiling.entity,
+            normal: ceiling.normal,
         });
+    }
+}
+
+/// Pushes dynamic rigid bodies the body ran into during [`collide_and_slide`], like an inelastic
+/// collision between the two would.
+fn push_dynamic_bodies(
+    mut commands: Commands,
+    bodies: Query<(&KinematicCharacterBody, &CharacterCollisions)>,
+    colliders: Query<&ColliderOf>,
+    dynamic_bodies: Query<(
+        &RigidBody,
+        &ComputedMass,
+        &LinearVelocity,
+        &AngularVelocity,
+        &Position,
+        &Rotation,
+        &ComputedCenterOfMass,
+    )>,
+    mut external_impulses: Query<&mut ExternalImpulse>,
+) {
+    // linear and angular impulse applied to each dynamic rigid body
+    let mut impulses = HashMap::<Entity, (Vec3, Vec3)>::default();
+    for (body, collisions) in &bodies {
+        if !body.push_dynamic_bodies {
+            continue;
+        }
+
+        let mut pushes = HashMap::<Entity, (Vec3, Vec3)>::default();
+        for collision in &collisions.0 {
+            let entity = colliders
+                .get(collision.entity)
+                .map_or(collision.entity, |collider_of| collider_of.body);
+            let Ok((
+                rigid_body,
+                mass,
+                linear_velocity,
+                angular_velocity,
+                position,
+                rotation,
+                center_of_mass,
+            )) = dynamic_bodies.get(entity)
+            else {
+                continue;
+            };
+            if !rigid_body.is_dynamic() {
+                continue;
+            }
+
+            let center_of_mass = world_center_of_mass(position, rotation, center_of_mass);
+            let point_velocity =
+                linear_velocity.0 + angular_velocity.0.cross(collision.point - center_of_mass);
+            // the normal points towards the character body
+            let approach_speed = (collision.velocity - point_velocity).dot(-collision.normal);
+            if approach_speed <= 0.0 {
+                continue;
+            }
+
+            let reduced_mass = body.mass * mass.value() / (body.mass + mass.value());
+            let impulse = -collision.normal * reduced_mass * approach_speed;
+            let push = pushes.entry(entity).or_default();
+            push.0 += impulse;
+            push.1 += (collision.point - center_of_mass).cross(impulse);
+        }
+
+        for (entity, (impulse, angular_impulse)) in pushes {
+            let length = impulse.length();
+            let scale = if length > body.max_push_impulse {
+                body.max_push_impulse / length
+            } else {
+                1.0
+            };
+            let total = impulses.entry(entity).or_default();
+            total.0 += impulse * scale;
+            total.1 += angular_impulse * scale;
+        }
+    }
+
+    // bodies without an `ExternalImpulse` get a single one, so impulses don't overwrite each other
+    for (entity, (impulse, angular_impulse)) in impulses {
+        match external_impulses.get_mut(entity) {
+            Ok(mut external_impulse) => {
+                external_impulse
+                    .apply_impulse(impulse)
+                    .apply_angular_impulse(angular_impulse);
+            }
+            Err(_) => {
+                let mut external_impulse = ExternalImpulse::default();
+                external_impulse
+                    .apply_impulse(impulse)
+                    .apply_angular_impulse(angular_impulse);
+                commands.entity(entity).insert(external_impulse);
+            }
+        }
+    }
+}
+
+/// Tries to move the body over an obstacle in front of it by sweeping the collider up by at most
+/// [`KinematicCharacterBody::max_step_height`], forward along the horizontal part of
+/// `remaining_velocity` and back down onto the top of the obstacle.
+///
+/// Returns the position on top of the obstacle and the part of `remaining_velocity` that is left
+/// after stepping, or `None` if the obstacle can't be stepped onto.
+#[allow(clippy::too_many_arguments)]
+fn step_up(
+    spatial_query: &SpatialQuery,
+    collider: &Collider,
+    body: &KinematicCharacterBody,
+    filter: &SpatialQueryFilter,
+    blocks: &dyn Fn(Entity, Vec3) -> bool,
+    position: Vec3,
+    rotation: Quat,
+    remaining_velocity: Vec3,
+) -> Option<(Vec3, Vec3)> {
+    let up = body.up;
+    let horizontal_velocity = remaining_velocity.reject_from_normalized(*up);
+    let horizontal_distance = horizontal_velocity.length();
+    if body.max_step_height <= 0.0 || horizontal_distance <= EPSILON {
+        return None;
+    }
+    let forward = horizontal_velocity / horizontal_distance;
+    let probe_distance = horizontal_distance.max(MIN_STEP_PROBE_DISTANCE);
+
+    // step up, as far as the ceiling allows
+    let step_height = match spatial_query.cast_shape_predicate(
+        collider,
+        position,
+        rotation,
+        up,
+        &ShapeCastConfig {
+            max_distance: body.max_step_height + EPSILON,
+            ..Default::default()
+        },
+        filter,
+        &|collider| blocks(collider, *up),
+    ) {
+        Some(hit) => hit.distance - EPSILON,
+        None => body.max_step_height,
+    };
+    if step_height <= EPSILON {
+        return None;
+    }
+    let raised_position = position + up * step_height;
+
+    // step forward
+    let forward_distance = match spatial_query.cast_shape_predicate(
+        collider,
+        raised_position,
+        rotation,
+        Dir3::new_unchecked(forward),
+        &ShapeCastConfig {
+            max_distance: probe_distance + EPSILON,
+            ..Default::default()
+        },
+        filter,
+        &|collider| blocks(collider, forward),
+    ) {
+        Some(hit) => (hit.distance - EPSILON).max(0.0),
+        None => probe_distance,
+    };
+    if forward_distance <= EPSILON {
+        return None;
+    }
+    // the body never moves further than it was going to
+    let forward_distance = forward_distance.min(horizontal_distance);
+    let advanced_position = raised_position + forward * forward_distance;
+
+    // step down onto the obstacle, the rest of the way down is left to `snap_to_ground`
+    let hit = spatial_query.cast_shape_predicate(
+        collider,
+        advanced_position,
+        rotation,
+        -up,
+        &ShapeCastConfig {
+            max_distance: step_height + EPSILON,
+            ..Default::default()
+        },
+        filter,
+        &|collider| blocks(collider, -*up),
+    )?;
+    let landing_height = step_height - hit.distance + EPSILON;
+    if landing_height <= EPSILON {
+        return None;
+    }
+
+    // the collider might rest on the edge of the obstacle, in which case the hit normal is tilted,
+    // so the surface right in front of the contact point is checked as well
+    if !body.is_walkable(hit.normal1) {
+        let surface_hit = spatial_query.cast_ray_predicate(
+            hit.point1 + forward * EPSILON * 10.0 + up * step_height,
+            -up,
+            2.0 * step_height,
+            true,
+            filter,
+            &|collider| blocks(collider, -*up),
+        )?;
+        if !body.is_walkable(surface_hit.normal) {
+            return None;
+        }
+    }
+
+    Some((
+        position + up * landing_height + forward * forward_distance,
+        forward * (horizontal_distance - forward_distance),
+    ))
 }
 
 pub fn collide_and_slide_debug_visualization(
-    bodies: Query<(&KinematicCharacterBody, &Collider, &Velocity, &Transform)>,
+    bodies: Query<(
+        Entity,
+        &KinematicCharacterBody,
+        &Collider,
+        &Velocity,
+        &Transform,
+    )>,
     spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
     max_iterations: Res<CollideAndSlideMaxIterations>,
     mut gizmos: Gizmos,
 ) {
-    for (_body, collider, velocity, transform) in &bodies {
+    for (entity, body, collider, velocity, transform) in &bodies {
+        let Some(adjusted_collider) = adjusted_collider(collider) else {
+            continue;
+        };
         let mut remaining_velocity = velocity.0;
-        let adjusted_collider = inflated_collider(collider, -EPSILON);
         let mut position = transform.translation;
 
-        let capsule = adjusted_collider.shape().as_capsule().unwrap();
-        gizmos.primitive_3d(
-            &Capsule3d::new(capsule.radius, capsule.height()),
-            Isometry3d::from_translation(position),
+        draw_collider(
+            &mut gizmos,
+            &adjusted_collider,
+            position,
+            transform.rotation,
             tailwind::GREEN_500,
         );
 
         let mut direction = remaining_velocity.normalize();
         let mut i = 0;
         while i < max_iterations.0 && remaining_velocity.length_squared() > 0.0 {
-            if let Some(hit) = spatial_query.cast_shape(
+            if let Some(hit) = spatial_query.cast_shape_predicate(
                 &adjusted_collider,
                 position,
-                Quat::IDENTITY,
+                transform.rotation,
                 Dir3::new_unchecked(direction),
                 &ShapeCastConfig {
                     max_distance: remaining_velocity.length() + EPSILON,
                     ..Default::default()
                 },
-                &SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
+                &body.filter(entity),
+                &|collider| body.collides_with(collider, &dynamic_bodies),
             ) {
                 let mut new_position = position + direction * hit.distance;
                 new_position += hit.normal1 * EPSILON;
 
                 gizmos.arrow(position, new_position, tailwind::RED_500);
-                gizmos.primitive_3d(
-                    &Capsule3d::new(capsule.radius, capsule.height()),
-                    Isometry3d::from_translation(new_position),
+                draw_collider(
+                    &mut gizmos,
+                    &adjusted_collider,
+                    new_position,
+                    transform.rotation,
                     tailwind::GREEN_500,
                 );
 
@@ -217,9 +1240,11 @@ pub fn collide_and_slide_debug_visualization(
             } else {
                 let new_position = position + remaining_velocity;
                 gizmos.arrow(position, new_position, tailwind::RED_500);
-                gizmos.primitive_3d(
-                    &Capsule3d::new(capsule.radius, capsule.height()),
-                    Isometry3d::from_translation(new_position),
+                draw_collider(
+                    &mut gizmos,
+                    &adjusted_collider,
+                    new_position,
+                    transform.rotation,
                     tailwind::GREEN_500,
                 );
                 break;
@@ -233,38 +1258,61 @@ pub fn collide_and_slide_debug_visualization(
 pub fn snap_to_ground(
     mut bodies: Query<
         (
+            Entity,
             &KinematicCharacterBody,
             &Collider,
             &Velocity,
             &mut Transform,
+            Has<DropThrough>,
         ),
         With<Grounded>,
     >,
     spatial_query: SpatialQuery,
+    one_way_platforms: OneWayPlatforms,
+    dynamic_bodies: DynamicBodies,
 ) {
-    bodies
-        .par_iter_mut()
-        .for_each(|(body, collider, velocity, mut transform)| {
+    bodies.par_iter_mut().for_each(
+        |(entity, body, collider, velocity, mut transform, is_dropping)| {
             // only snap character to ground if vertical velocity is negative to allow for jumping
-            if !body.snap_to_ground || velocity.y > 0.0 {
+            if !body.snap_to_ground || velocity.dot(*body.up) > 0.0 {
                 return;
             }
 
-            let adjusted_collider = inflated_collider(collider, -EPSILON);
-            if let Some(hit) = spatial_query.cast_shape(
+            let Some(adjusted_collider) = adjusted_collider(collider) else {
+                return;
+            };
+            // the snapping distance has to cover at least one step, so bodies walking down stairs
+            // stay on them
+            let max_distance = body.snap_to_ground_max_distance.max(body.max_step_height);
+            let filter = body.filter(entity);
+            let passing = one_way_platforms.overlapping(
+                &spatial_query,
                 &adjusted_collider,
                 transform.translation,
-                Quat::IDENTITY,
-                Dir3::new_unchecked(Vec3::NEG_Y),
+                transform.rotation,
+                &filter,
+            );
+            if let Some(hit) = spatial_query.cast_shape_predicate(
+                &adjusted_collider,
+                transform.translation,
+                transform.rotation,
+                -body.up,
                 &ShapeCastConfig {
-                    max_distance: body.snap_to_ground_max_distance + EPSILON,
+                    max_distance: max_distance + EPSILON,
                     ..Default::default()
                 },
-                &SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
+                &filter,
+                &|collider| {
+                    body.collides_with(collider, &dynamic_bodies)
+                        && one_way_platforms.blocks(collider, velocity.0, &passing, is_dropping)
+                },
             ) {
-                transform.translation.y -= hit.distance - EPSILON;
+                if body.is_walkable(hit.normal1) {
+                    transform.translation -= body.up * (hit.distance - EPSILON);
+                }
             }
-        });
+        },
+    );
 }
 
 fn respond_to_ground(
@@ -275,41 +1323,317 @@ fn respond_to_ground(
         &Collider,
         &Transform,
         &mut Velocity,
+        &mut GroundVelocity,
+        Option<&GroundInfo>,
+        Has<Sliding>,
+        Has<DropThrough>,
+        Has<IgnoreGround>,
+    )>,
+    colliders: Query<&ColliderOf>,
+    grounds: Query<(
+        &LinearVelocity,
+        &AngularVelocity,
+        &Position,
+        &Rotation,
+        &ComputedCenterOfMass,
     )>,
+    surfaces: Query<&SurfaceMaterial>,
     spatial_query: SpatialQuery,
+    one_way_platforms: OneWayPlatforms,
+    dynamic_bodies: DynamicBodies,
+    mut sliding_started: EventWriter<SlidingStarted>,
+    mut sliding_stopped: EventWriter<SlidingStopped>,
+    time: Res<Time>,
 ) {
-    for (entity, body, collider, transform, mut velocity) in controllers.iter_mut() {
-        let adjusted_collider = inflated_collider(collider, -EPSILON);
-        let mut is_grounded = false;
-        if let Some(hit) = spatial_query.cast_shape(
+    for (
+        entity,
+        body,
+        collider,
+        transform,
+        mut velocity,
+        mut ground_velocity,
+        previous_ground,
+        was_sliding,
+        is_dropping,
+        ignores_ground,
+    ) in controllers.iter_mut()
+    {
+        let Some(adjusted_collider) = adjusted_collider(collider) else {
+            continue;
+        };
+        let filter = body.filter(entity);
+        let passing = one_way_platforms.overlapping(
+            &spatial_query,
             &adjusted_collider,
             transform.translation,
             transform.rotation,
-            Dir3::new_unchecked(-Vec3::Y),
-            &ShapeCastConfig {
+            &filter,
+        );
+        let mut ground = None;
+        let mut slope_normal = None;
+        let hit = spatial_query.cast_shape_predicate(
+            &adjusted_collider,
+            transform.translation,
+            transform.rotation,
+            -body.up,
+            &ShapeCastConfig {
                 max_distance: body.grounded_max_distance + EPSILON,
                 ..Default::default()
             },
-            &SpatialQueryFilter::from_mask(CollisionLayer::Terrain),
-        ) {
-            let ground_angle = hit.normal1.angle_between(Vec3::Y);
-            is_grounded = ground_angle <= body.max_terrain_slope;
+            &filter,
+            // bodies moving up through a platform aren't grounded on it
+            &|collider| {
+                body.collides_with(collider, &dynamic_bodies)
+                    && one_way_platforms.blocks(collider, velocity.0, &passing, is_dropping)
+            },
+        );
+        if let Some(hit) = hit.filter(|_| !ignores_ground) {
+            if body.is_walkable(hit.normal1) {
+                // the hit entity is the collider, which might be a child of the actual rigid body
+                let ground_entity = colliders
+                    .get(hit.entity)
+                    .map_or(hit.entity, |collider_of| collider_of.body);
+                ground = Some(GroundInfo {
+                    entity: ground_entity,
+                    normal: hit.normal1,
+                    slope_angle: hit.normal1.angle_between(*body.up),
+                    distance: hit.distance,
+                    landed_at: previous_ground
+                        .map_or(time.elapsed_secs(), |ground| ground.landed_at),
+                    surface: surfaces
+                        .get(hit.entity)
+                        .or_else(|_| surfaces.get(ground_entity))
+                        .copied()
+                        .unwrap_or_default(),
+                });
+            } else {
+                slope_normal = Some(hit.normal1);
+            }
         }
 
-        if is_grounded {
-            velocity.y = 0.0;
-            commands.entity(entity).insert(Grounded);
+        match slope_normal {
+            Some(normal) => {
+                if !was_sliding {
+                    sliding_started.write(SlidingStarted { entity });
+                }
+                commands.entity(entity).insert(Sliding { normal });
+            }
+            None if was_sliding => {
+                sliding_stopped.write(SlidingStopped { entity });
+                commands.entity(entity).remove::<Sliding>();
+            }
+            None => {}
+        }
+
+        if let Some(ground) = ground {
+            velocity.0 = velocity.reject_from_normalized(*body.up);
+            ground_velocity.0 = grounds.get(ground.entity).map_or(
+                Vec3::ZERO,
+                |(linear_velocity, angular_velocity, position, rotation, center_of_mass)| {
+                    let center_of_mass = world_center_of_mass(position, rotation, center_of_mass);
+                    linear_velocity.0
+                        + angular_velocity
+                            .0
+                            .cross(transform.translation - center_of_mass)
+                },
+            );
+            commands
+                .entity(entity)
+                .insert((Grounded, ground))
+                .remove::<LastGround>();
+        } else {
+            velocity.0 += ground_velocity.0;
+            ground_velocity.0 = Vec3::ZERO;
+            commands.entity(entity).remove::<(Grounded, GroundInfo)>();
+            if let Some(previous_ground) = previous_ground {
+                commands.entity(entity).insert(LastGround {
+                    info: *previous_ground,
+                    left_at: time.elapsed_secs(),
+                });
+            }
+        }
+    }
+}
+
+/// Adds the [`SurfaceMaterial`]s from [`SurfaceMaterialNames`] to meshes loaded from glTF files.
+fn assign_surface_materials(
+    mut commands: Commands,
+    meshes: Query<(Entity, &GltfMaterialName), Added<GltfMaterialName>>,
+    names: Res<SurfaceMaterialNames>,
+) {
+    for (entity, material_name) in &meshes {
+        if let Some(surface) = names.0.get(&material_name.0) {
+            commands.entity(entity).insert(*surface);
+        }
+    }
+}
+
+/// Adds the [`WaterVolume`]s from [`WaterMaterialNames`] to meshes loaded from glTF files.
+fn assign_water_volumes(
+    mut commands: Commands,
+    meshes: Query<(Entity, &GltfMaterialName), Added<GltfMaterialName>>,
+    names: Res<WaterMaterialNames>,
+) {
+    for (entity, material_name) in &meshes {
+        if let Some(water) = names.0.get(&material_name.0) {
+            commands.entity(entity).insert(*water);
+        }
+    }
+}
+
+/// Turns the colliders of [`WaterVolume`]s and [`Climbable`]s into sensors on the water and trigger
+/// layers. Colliders created by a [`ColliderConstructorHierarchy`] only appear once their mesh is
+/// loaded and get the hierarchy's layers, so this keeps checking all volumes.
+fn configure_sensor_volumes(
+    mut commands: Commands,
+    volumes: Query<
+        (
+            Entity,
+            Option<&CollisionLayers>,
+            Has<Sensor>,
+            Has<WaterVolume>,
+        ),
+        Or<(With<WaterVolume>, With<Climbable>)>,
+    >,
+) {
+    for (entity, current_layers, is_sensor, is_water) in &volumes {
+        let layer = if is_water {
+            CollisionLayer::Water
         } else {
-            commands.entity(entity).remove::<Grounded>();
+            CollisionLayer::Trigger
+        };
+        let layers = CollisionLayers::new(layer, LayerMask::ALL);
+        if !is_sensor || current_layers != Some(&layers) {
+            commands.entity(entity).insert((Sensor, layers));
+        }
+    }
+}
+
+/// Fraction of `body`'s height along `up` below the surface of the water volume `water`, and the
+/// height of the surface along `up`. Returns `None` if the body doesn't reach into the volume.
+fn submersion(body: &ColliderAabb, water: &ColliderAabb, up: Dir3) -> Option<(f32, f32)> {
+    // lowest and highest points of a box along `up`
+    let extent = |aabb: &ColliderAabb| {
+        let center = (aabb.min + aabb.max) * 0.5;
+        let half_height = ((aabb.max - aabb.min) * 0.5).dot(up.abs());
+        (center.dot(*up) - half_height, center.dot(*up) + half_height)
+    };
+    let (body_bottom, body_top) = extent(body);
+    let (water_bottom, surface_height) = extent(water);
+
+    // the body's center, moved along `up` into the middle of the water, has to be inside of it
+    let center = (body.min + body.max) * 0.5;
+    let water_middle = (water_bottom + surface_height) * 0.5;
+    let probe = center + up * (water_middle - center.dot(*up));
+    let is_inside_sideways =
+        probe.cmpge(water.min - EPSILON).all() && probe.cmple(water.max + EPSILON).all();
+    if !is_inside_sideways || body_top < water_bottom {
+        return None;
+    }
+
+    let height = (body_top - body_bottom).max(EPSILON);
+    let fraction = ((surface_height - body_bottom) / height).clamp(0.0, 1.0);
+    (fraction > 0.0).then_some((fraction, surface_height))
+}
+
+/// Finds the deepest [`WaterVolume`] each character body is in and updates its [`Submerged`].
+fn detect_water(
+    mut commands: Commands,
+    bodies: Query<(
+        Entity,
+        &KinematicCharacterBody,
+        &Collider,
+        &Transform,
+        Has<Submerged>,
+    )>,
+    volumes: Query<(Entity, &WaterVolume, &ColliderAabb)>,
+) {
+    for (entity, body, collider, transform, is_submerged) in &bodies {
+        // the body's own aabb is only updated by the physics step, after the body already moved
+        let aabb = collider.aabb(transform.translation, transform.rotation);
+        let submerged = volumes
+            .iter()
+            .filter_map(|(volume, water, water_aabb)| {
+                let (fraction, surface_height) = submersion(&aabb, water_aabb, body.up)?;
+                Some(Submerged {
+                    volume,
+                    water: *water,
+                    surface_height,
+                    fraction,
+                })
+            })
+            .max_by(|a, b| a.fraction.total_cmp(&b.fraction));
+
+        match submerged {
+            Some(submerged) => {
+                commands.entity(entity).insert(submerged);
+            }
+            None if is_submerged => {
+                commands.entity(entity).remove::<Submerged>();
+            }
+            None => {}
+        }
+    }
+}
+
+/// Pushes dynamic rigid bodies in [`WaterVolume`]s up towards the surface and slows them down.
+fn float_dynamic_bodies(
+    mut bodies: Query<(
+        &RigidBody,
+        &ColliderAabb,
+        &mut LinearVelocity,
+        &mut AngularVelocity,
+    )>,
+    volumes: Query<(&WaterVolume, &ColliderAabb)>,
+    gravity: Res<Gravity>,
+    time: Res<Time>,
+) {
+    let delta_secs = time.delta_secs();
+    let up = Dir3::new(-gravity.0).unwrap_or(Dir3::Y);
+    for (rigid_body, aabb, mut linear_velocity, mut angular_velocity) in &mut bodies {
+        if !rigid_body.is_dynamic() {
+            continue;
+        }
+        for (water, water_aabb) in &volumes {
+            let Some((fraction, _)) = submersion(aabb, water_aabb, up) else {
+                continue;
+            };
+            linear_velocity.0 -= gravity.0 * water.buoyancy * fraction * delta_secs;
+            let drag = (1.0 - water.drag * fraction * delta_secs).max(0.0);
+            linear_velocity.0 *= drag;
+            angular_velocity.0 *= drag;
+        }
+    }
+}
+
+fn update_drop_through(
+    mut commands: Commands,
+    mut bodies: Query<(Entity, &mut DropThrough)>,
+    time: Res<Time>,
+) {
+    for (entity, mut drop_through) in &mut bodies {
+        drop_through.remaining -= time.delta_secs();
+        if drop_through.remaining <= 0.0 {
+            commands.entity(entity).remove::<DropThrough>();
         }
     }
 }
 
-fn distance_from_center_to_hull(
+fn world_center_of_mass(
+    position: &Position,
+    rotation: &Rotation,
+    center_of_mass: &ComputedCenterOfMass,
+) -> Vec3 {
+    position.0 + rotation.0 * center_of_mass.0
+}
+
+/// Distance from the center of `collider` to its surface in `direction`. Returns `None` if the ray
+/// cast against the collider's shape doesn't hit it, e.g. for unsupported shapes.
+pub fn distance_from_center_to_hull(
     collider: &Collider,
     collider_rotation: Quat,
     direction: Dir3,
-) -> f32 {
+) -> Option<f32> {
     let aabb = collider.aabb(Vec3::splat(0.0), collider_rotation);
     let max_distance = aabb.min.length().max(aabb.max.length());
 
@@ -322,20 +1646,993 @@ fn distance_from_center_to_hull(
             max_distance,
             false,
         )
-        .expect("collider not supported")
-        .0
+        .map(|(distance, _)| distance)
 }
 
-fn inflated_collider(collider: &Collider, size: f32) -> Collider {
-    if let Some(ball) = collider.shape().as_ball() {
-        Collider::sphere(ball.radius + size)
-    } else if let Some(capsule) = collider.shape().as_capsule() {
+/// Returns a copy of `collider` with its surface moved outwards by `size`, or inwards for negative
+/// sizes. Convex hulls are scaled about their center, which moves every face by at least `size`.
+pub fn inflated_collider(collider: &Collider, size: f32) -> Result<Collider, InflateColliderError> {
+    let shape = collider.shape();
+    if let Some(ball) = shape.as_ball() {
+        let extended_radius = ball.radius + size;
+        if extended_radius <= 0.0 {
+            return Err(InflateColliderError::Degenerate);
+        }
+        Ok(Collider::sphere(extended_radius))
+    } else if let Some(capsule) = shape.as_capsule() {
         let extended_radius = capsule.radius + size;
         let inclusive_height = capsule.height() + 2.0 * capsule.radius;
         let inclusive_extended_height = inclusive_height + 2.0 * size;
         let extended_height = inclusive_extended_height - 2.0 * extended_radius;
-        Collider::capsule(extended_radius, extended_height)
+        if extended_radius <= 0.0 {
+            return Err(InflateColliderError::Degenerate);
+        }
+        Ok(Collider::capsule(extended_radius, extended_height))
+    } else if let Some(cuboid) = shape.as_cuboid() {
+        let half_extents = cuboid.half_extents;
+        let extended_size =
+            2.0 * (Vec3::new(half_extents.x, half_extents.y, half_extents.z) + size);
+        if extended_size.min_element() <= 0.0 {
+            return Err(InflateColliderError::Degenerate);
+        }
+        Ok(Collider::cuboid(
+            extended_size.x,
+            extended_size.y,
+            extended_size.z,
+        ))
+    } else if let Some(cylinder) = shape.as_cylinder() {
+        let extended_radius = cylinder.radius + size;
+        let extended_height = 2.0 * (cylinder.half_height + size);
+        if extended_radius <= 0.0 || extended_height <= 0.0 {
+            return Err(InflateColliderError::Degenerate);
+        }
+        Ok(Collider::cylinder(extended_radius, extended_height))
+    } else if let Some(polyhedron) = shape.as_convex_polyhedron() {
+        let points: Vec<Vec3> = polyhedron
+            .points()
+            .iter()
+            .map(|point| Vec3::new(point.x, point.y, point.z))
+            .collect();
+        let center = points.iter().sum::<Vec3>() / points.len() as f32;
+        // for convex shapes, the closest point on the surface lies on the closest face
+        let (closest_point, _) = collider.project_point(Vec3::ZERO, Quat::IDENTITY, center, false);
+        let scale = 1.0 + size / center.distance(closest_point);
+        if scale <= 0.0 {
+            return Err(InflateColliderError::Degenerate);
+        }
+        Collider::convex_hull(
+            points
+                .iter()
+                .map(|point| center + (*point - center) * scale)
+                .collect(),
+        )
+        .ok_or(InflateColliderError::Degenerate)
     } else {
-        panic!("unsupported shape");
+        Err(InflateColliderError::UnsupportedShape(shape.shape_type()))
+    }
+}
+
+/// Shrinks `collider` by [`EPSILON`], which keeps casts from starting inside of surfaces the body is
+/// resting on. Returns `None` for colliders that can't be used for character bodies.
+pub fn adjusted_collider(collider: &Collider) -> Option<Collider> {
+    inflated_collider(collider, -EPSILON)
+        .inspect_err(|error| warn_once!("character body collider can't be used: {error}"))
+        .ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum InflateColliderError {
+    UnsupportedShape(ShapeType),
+    /// the collider would have no volume left after shrinking it
+    Degenerate,
+}
+
+impl std::fmt::Display for InflateColliderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedShape(shape_type) => write!(f, "unsupported shape {shape_type:?}"),
+            Self::Degenerate => write!(f, "collider is too small to be shrunk"),
+        }
+    }
+}
+
+impl std::error::Error for InflateColliderError {}
+
+fn draw_collider(
+    gizmos: &mut Gizmos,
+    collider: &Collider,
+    position: Vec3,
+    rotation: Quat,
+    color: impl Into<Color>,
+) {
+    let shape = collider.shape();
+    let isometry = Isometry3d::new(position, rotation);
+    let color = color.into();
+    if let Some(ball) = shape.as_ball() {
+        gizmos.primitive_3d(&Sphere::new(ball.radius), isometry, color);
+    } else if let Some(capsule) = shape.as_capsule() {
+        gizmos.primitive_3d(
+            &Capsule3d::new(capsule.radius, capsule.height()),
+            isometry,
+            color,
+        );
+    } else if let Some(cuboid) = shape.as_cuboid() {
+        gizmos.primitive_3d(
+            &Cuboid::new(
+                2.0 * cuboid.half_extents.x,
+                2.0 * cuboid.half_extents.y,
+                2.0 * cuboid.half_extents.z,
+            ),
+            isometry,
+            color,
+        );
+    } else if let Some(cylinder) = shape.as_cylinder() {
+        gizmos.primitive_3d(
+            &Cylinder::new(cylinder.radius, 2.0 * cylinder.half_height),
+            isometry,
+            color,
+        );
+    } else if let Some(polyhedron) = shape.as_convex_polyhedron() {
+        let points = polyhedron.points();
+        for edge in polyhedron.edges() {
+            let [start, end] = [edge.vertices.x, edge.vertices.y].map(|index| {
+                let point = points[index as usize];
+                position + rotation * Vec3::new(point.x, point.y, point.z)
+            });
+            gizmos.line(start, end, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::*;
+
+    #[test]
+    fn climbs_stairs() {
+        let mut app = test_app();
+        spawn_stairs(&mut app, 2.0, 4, 0.2, 0.4);
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        let mut max_vertical_velocity = f32::MIN;
+        for _ in 0..90 {
+            app.update();
+            let velocity = app.world().get::<Velocity>(body).unwrap();
+            max_vertical_velocity = max_vertical_velocity.max(velocity.y);
+        }
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(translation.x > 4.0, "body got stuck at {translation}");
+        assert!(
+            (translation.y - (0.8 + BODY_HALF_HEIGHT)).abs() < 0.05,
+            "body is not standing on the top step: {translation}"
+        );
+        assert!(max_vertical_velocity <= 0.0);
+        assert!(app.world().get::<Grounded>(body).is_some());
+    }
+
+    #[test]
+    fn climbs_curb() {
+        let mut app = test_app();
+        spawn_stairs(&mut app, 1.0, 1, 0.2, 10.0);
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(translation.x > 2.5, "body got stuck at {translation}");
+        assert!((translation.y - (0.2 + BODY_HALF_HEIGHT)).abs() < 0.05);
+        assert!(app.world().get::<Velocity>(body).unwrap().y <= 0.0);
+    }
+
+    #[test]
+    fn does_not_climb_obstacles_higher_than_max_step_height() {
+        let mut app = test_app();
+        spawn_stairs(&mut app, 2.0, 1, 0.5, 10.0);
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        run(&mut app, 90);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(
+            translation.x < 2.0 - 0.3 + 0.01,
+            "body passed the wall: {translation}"
+        );
+        assert!((translation.y - BODY_HALF_HEIGHT).abs() < 0.05);
+    }
+
+    #[test]
+    fn rides_moving_platform() {
+        let mut app = test_app();
+        let platform = app
+            .world_mut()
+            .spawn((
+                RigidBody::Kinematic,
+                Collider::cuboid(4.0, 1.0, 4.0),
+                CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+                Transform::from_xyz(0.0, -0.5, 0.0),
+                LinearVelocity(Vec3::X),
+                AngularVelocity(Vec3::Y * 0.5),
+            ))
+            .id();
+        let body = spawn_body(&mut app, Vec3::ZERO);
+
+        run(&mut app, 60);
+
+        let platform_translation = app.world().get::<Transform>(platform).unwrap().translation;
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(platform_translation.x > 0.5);
+        assert!(
+            translation.xz().distance(platform_translation.xz()) < 0.1,
+            "body at {translation} was left behind by platform at {platform_translation}"
+        );
+        assert_eq!(
+            app.world()
+                .get::<GroundInfo>(body)
+                .map(|ground| ground.entity),
+            Some(platform)
+        );
+        let (yaw, _, _) = app
+            .world()
+            .get::<Transform>(body)
+            .unwrap()
+            .rotation
+            .to_euler(EulerRot::YXZ);
+        assert!(yaw > 0.3, "body did not inherit the platform's yaw");
+    }
+
+    #[test]
+    fn walks_on_ceiling_with_inverted_up() {
+        let mut app = test_app();
+        spawn_terrain(
+            &mut app,
+            Vec3::new(40.0, 1.0, 40.0),
+            Vec3::new(0.0, 0.5, 0.0),
+        );
+        let body = app
+            .world_mut()
+            .spawn((
+                KinematicCharacterBody {
+                    up: Dir3::NEG_Y,
+                    ..Default::default()
+                },
+                Collider::capsule(0.3, 1.3),
+                CollisionLayers::new(CollisionLayer::Player, LayerMask::ALL),
+                Transform::from_xyz(0.0, -BODY_HALF_HEIGHT - 0.01, 0.0),
+                Velocity(Vec3::new(2.0, 1.0, 0.0)),
+            ))
+            .id();
+
+        run(&mut app, 30);
+
+        let transform = app.world().get::<Transform>(body).unwrap();
+        assert!(app.world().get::<Grounded>(body).is_some());
+        assert!((transform.translation.y + BODY_HALF_HEIGHT).abs() < 0.05);
+        assert!(transform.translation.x > 0.5);
+        assert!(transform.up().dot(Vec3::NEG_Y) > 0.99);
+        assert!(app.world().get::<Velocity>(body).unwrap().y.abs() < EPSILON);
+    }
+
+    #[test]
+    fn shrinks_supported_shapes() {
+        let cuboid = inflated_collider(&Collider::cuboid(1.0, 2.0, 3.0), -0.1).unwrap();
+        let half_extents = cuboid.shape().as_cuboid().unwrap().half_extents;
+        assert!((half_extents.x - 0.4).abs() < EPSILON);
+        assert!((half_extents.z - 1.4).abs() < EPSILON);
+
+        let cylinder = inflated_collider(&Collider::cylinder(0.5, 2.0), -0.1).unwrap();
+        let cylinder = cylinder.shape().as_cylinder().unwrap();
+        assert!((cylinder.radius - 0.4).abs() < EPSILON);
+        assert!((cylinder.half_height - 0.9).abs() < EPSILON);
+
+        let points = vec![
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 2.0, 0.0),
+        ];
+        let hull = Collider::convex_hull(points.clone()).unwrap();
+        let shrunk_hull = inflated_collider(&hull, -0.1).unwrap();
+        for point in points {
+            let (projected, _) = shrunk_hull.project_point(Vec3::ZERO, Quat::IDENTITY, point, true);
+            assert!(point.distance(projected) >= 0.1 - EPSILON);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_and_degenerate_shapes() {
+        let triangle = Collider::triangle(Vec3::ZERO, Vec3::X, Vec3::Y);
+        assert!(matches!(
+            inflated_collider(&triangle, -EPSILON),
+            Err(InflateColliderError::UnsupportedShape(_))
+        ));
+        assert!(matches!(
+            inflated_collider(&Collider::sphere(0.1), -0.2),
+            Err(InflateColliderError::Degenerate)
+        ));
+    }
+
+    #[test]
+    fn reports_wall_collisions() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let wall = app
+            .world_mut()
+            .spawn((
+                RigidBody::Static,
+                Collider::cuboid(1.0, 4.0, 4.0),
+                CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+                Transform::from_xyz(2.0, 2.0, 0.0),
+            ))
+            .id();
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        let mut wall_collision = None;
+        for _ in 0..60 {
+            app.update();
+            let collisions = app.world().get::<CharacterCollisions>(body).unwrap();
+            wall_collision = wall_collision.or(collisions
+                .0
+                .iter()
+                .find(|collision| collision.entity == wall)
+                .copied());
+        }
+
+        let wall_collision = wall_collision.expect("wall collision was not reported");
+        assert!(wall_collision.normal.dot(Vec3::NEG_X) > 0.99);
+        assert!((wall_collision.point.x - 1.5).abs() < 0.01);
+        assert_eq!(wall_collision.velocity, Vec3::X * 3.0);
+        assert_eq!(wall_collision.iteration, 0);
+    }
+
+    #[test]
+    fn pushes_dynamic_bodies() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let ball = app
+            .world_mut()
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::sphere(0.5),
+                Transform::from_xyz(2.0, 0.5, 0.0),
+            ))
+            .id();
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .push_dynamic_bodies = true;
+
+        run(&mut app, 60);
+
+        let ball_translation = app.world().get::<Transform>(ball).unwrap().translation;
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(ball_translation.x > 2.5, "ball was not pushed");
+        assert!(translation.x < ball_translation.x);
+        assert!(app.world().get::<LinearVelocity>(ball).unwrap().x > 0.0);
+    }
+
+    #[test]
+    fn passes_through_dynamic_bodies_without_pushing_them() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        app.world_mut().spawn((
+            RigidBody::Dynamic,
+            Collider::sphere(0.5),
+            CollisionLayers::new(CollisionLayer::Prop, LayerMask::ALL),
+            Transform::from_xyz(2.0, 0.5, 0.0),
+        ));
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .push_dynamic_bodies = false;
+
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(translation.x > 2.5, "body was stopped at {translation}");
+    }
+
+    #[test]
+    fn collides_with_static_props_without_pushing_dynamic_bodies() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        app.world_mut().spawn((
+            RigidBody::Static,
+            Collider::cuboid(1.0, 4.0, 40.0),
+            CollisionLayers::new(CollisionLayer::Prop, LayerMask::ALL),
+            Transform::from_xyz(2.0, 2.0, 0.0),
+        ));
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!((translation.x - (1.5 - 0.3)).abs() < 0.01);
+    }
+
+    #[test]
+    fn pushes_bodies_out_of_terrain() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+        app.world_mut()
+            .get_mut::<Transform>(body)
+            .unwrap()
+            .translation
+            .y = BODY_HALF_HEIGHT - 0.2;
+
+        run(&mut app, 3);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!((translation.y - BODY_HALF_HEIGHT).abs() < 0.01);
+        assert!(app.world().get::<Grounded>(body).is_some());
+    }
+
+    #[test]
+    fn leaves_overlapping_dynamic_bodies_to_the_solver() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+        let start = app.world().get::<Transform>(body).unwrap().translation;
+        app.world_mut().spawn((
+            RigidBody::Dynamic,
+            Collider::sphere(0.5),
+            CollisionLayers::new(CollisionLayer::Prop, LayerMask::ALL),
+            Transform::from_translation(start + Vec3::X * 0.3),
+        ));
+
+        run(&mut app, 3);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(
+            translation.xz().distance(start.xz()) < 0.01,
+            "body was pushed to {translation}"
+        );
+        assert!(app
+            .world()
+            .resource::<Events<DepenetrationFailed>>()
+            .is_empty());
+    }
+
+    #[test]
+    fn reports_failed_depenetration() {
+        let mut app = test_app();
+        app.world_mut()
+            .insert_resource(DepenetrationMaxIterations(0));
+        spawn_floor(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+        app.world_mut()
+            .get_mut::<Transform>(body)
+            .unwrap()
+            .translation
+            .y = BODY_HALF_HEIGHT - 0.2;
+
+        run(&mut app, 3);
+
+        let events = app.world().resource::<Events<DepenetrationFailed>>();
+        let failure = events
+            .iter_current_update_events()
+            .next()
+            .expect("no DepenetrationFailed event was sent");
+        assert_eq!(failure.entity, body);
+        assert!((failure.penetration - 0.2).abs() < 0.01);
+    }
+
+    #[test]
+    fn slides_on_steep_slopes() {
+        let mut app = test_app();
+        let (_, start) = spawn_slope(&mut app, 60f32.to_radians());
+        let normal = Quat::from_rotation_z(60f32.to_radians()) * Vec3::Y;
+        let body = spawn_body_at(&mut app, start, Vec3::ZERO);
+
+        let mut sliding_started = 0;
+        for _ in 0..5 {
+            app.update();
+            sliding_started += app
+                .world()
+                .resource::<Events<SlidingStarted>>()
+                .iter_current_update_events()
+                .count();
+        }
+
+        let sliding = app
+            .world()
+            .get::<Sliding>(body)
+            .expect("body is not sliding");
+        assert!(sliding.normal.dot(normal) > 0.99);
+        assert!(app.world().get::<Grounded>(body).is_none());
+        assert_eq!(sliding_started, 1);
+    }
+
+    #[test]
+    fn reports_ground_info() {
+        let mut app = test_app();
+        let (ground, start) = spawn_slope(&mut app, 20f32.to_radians());
+        let normal = Quat::from_rotation_z(20f32.to_radians()) * Vec3::Y;
+        let body = spawn_body_at(&mut app, start, Vec3::ZERO);
+
+        run(&mut app, 3);
+        let landed_at = app.world().get::<GroundInfo>(body).unwrap().landed_at;
+        run(&mut app, 3);
+
+        let ground_info = app.world().get::<GroundInfo>(body).unwrap();
+        assert_eq!(ground_info.entity, ground);
+        assert!(ground_info.normal.dot(normal) > 0.99);
+        assert!((ground_info.slope_angle - 20f32.to_radians()).abs() < 0.01);
+        assert!(ground_info.distance < 0.05);
+        assert_eq!(ground_info.landed_at, landed_at);
+    }
+
+    #[test]
+    fn records_last_ground_after_walking_off_a_ledge() {
+        let mut app = test_app();
+        let ledge = spawn_terrain(
+            &mut app,
+            Vec3::new(2.0, 1.0, 2.0),
+            Vec3::new(0.0, -0.5, 0.0),
+        );
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+
+        run(&mut app, 60);
+
+        assert!(app.world().get::<Grounded>(body).is_none());
+        let last_ground = app
+            .world()
+            .get::<LastGround>(body)
+            .expect("last ground was not recorded");
+        assert_eq!(last_ground.info.entity, ledge);
+        assert!(last_ground.left_at > 0.0);
+    }
+
+    #[test]
+    fn ceiling_cancels_upward_velocity() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let ceiling = app
+            .world_mut()
+            .spawn((
+                RigidBody::Static,
+                Collider::cuboid(40.0, 1.0, 40.0),
+                CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+                Transform::from_xyz(0.0, 2.0 * BODY_HALF_HEIGHT + 0.5 + 0.3, 0.0),
+            ))
+            .id();
+        let body = spawn_body(&mut app, Vec3::new(1.0, 5.0, 0.0));
+
+        let mut ceiling_hits = Vec::new();
+        for _ in 0..30 {
+            app.update();
+            ceiling_hits.extend(
+                app.world()
+                    .resource::<Events<HitCeiling>>()
+                    .iter_current_update_events()
+                    .map(|hit| (hit.entity, hit.ceiling)),
+            );
+        }
+
+        assert_eq!(ceiling_hits, vec![(body, ceiling)]);
+        let velocity = app.world().get::<Velocity>(body).unwrap();
+        assert_eq!(velocity.y, 0.0);
+        assert_eq!(velocity.x, 1.0);
+    }
+
+    #[test]
+    fn clips_velocity_against_walls() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+        let body = spawn_body(&mut app, Vec3::new(3.0, 0.0, 1.0));
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .velocity_response = VelocityResponse::Clip;
+
+        run(&mut app, 60);
+
+        let velocity = app.world().get::<Velocity>(body).unwrap();
+        assert!(
+            velocity.x.abs() < EPSILON,
+            "velocity into the wall: {velocity:?}"
+        );
+        assert!((velocity.z - 1.0).abs() < EPSILON);
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!((translation.x - (1.5 - 0.3)).abs() < 0.01);
+    }
+
+    #[test]
+    fn clips_velocity_in_corners() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+        spawn_wall(
+            &mut app,
+            Vec3::new(40.0, 4.0, 1.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        );
+        let body = spawn_body(&mut app, Vec3::new(3.0, 0.0, 2.0));
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .velocity_response = VelocityResponse::Clip;
+
+        run(&mut app, 60);
+
+        let velocity = app.world().get::<Velocity>(body).unwrap();
+        assert!(
+            velocity.length() < EPSILON,
+            "body keeps velocity {velocity:?}"
+        );
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!((translation.x - (1.5 - 0.3)).abs() < 0.01);
+        assert!((translation.z - (1.5 - 0.3)).abs() < 0.01);
+    }
+
+    #[test]
+    fn keeps_velocity_by_default() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+        let body = spawn_body(&mut app, Vec3::new(3.0, 0.0, 1.0));
+
+        run(&mut app, 60);
+
+        let velocity = app.world().get::<Velocity>(body).unwrap();
+        assert_eq!(velocity.0, Vec3::new(3.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn bounces_off_walls() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+        let body = spawn_body(&mut app, Vec3::new(3.0, 0.0, 1.0));
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .velocity_response = VelocityResponse::Bounce(0.5);
+
+        run(&mut app, 60);
+
+        let velocity = app.world().get::<Velocity>(body).unwrap();
+        assert!((velocity.x + 1.5).abs() < EPSILON, "velocity {velocity:?}");
+        assert!((velocity.z - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn passes_through_filtered_colliders() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let barrier = spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        );
+        let body = spawn_body(&mut app, Vec3::X * 3.0);
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .collision_filter =
+            CharacterFilter::default().with_predicate(move |collider| collider != barrier);
+
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(translation.x > 2.5, "body was stopped at {translation}");
+    }
+
+    fn spawn_one_way_platform(app: &mut App) {
+        let platform = spawn_terrain(app, Vec3::new(4.0, 0.2, 4.0), Vec3::new(0.0, 2.5, 0.0));
+        app.world_mut()
+            .entity_mut(platform)
+            .insert(OneWayPlatform::default());
+    }
+
+    #[test]
+    fn jumps_up_through_one_way_platforms() {
+        let mut app = test_app();
+        spawn_one_way_platform(&mut app);
+        let body = spawn_body(&mut app, Vec3::Y * 5.0);
+
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(translation.y > 5.0, "body was stopped at {translation}");
+
+        app.world_mut().get_mut::<Velocity>(body).unwrap().0 = Vec3::NEG_Y * 5.0;
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!((translation.y - (2.6 + BODY_HALF_HEIGHT)).abs() < 0.01);
+        assert!(app.world().get::<Grounded>(body).is_some());
+    }
+
+    #[test]
+    fn drops_through_one_way_platforms() {
+        let mut app = test_app();
+        spawn_one_way_platform(&mut app);
+        let body = spawn_body_at(
+            &mut app,
+            Vec3::Y * (2.6 + BODY_HALF_HEIGHT + 0.01),
+            Vec3::ZERO,
+        );
+        run(&mut app, 10);
+        assert!(app.world().get::<Grounded>(body).is_some());
+
+        app.world_mut()
+            .entity_mut(body)
+            .insert((DropThrough::default(), Velocity(Vec3::NEG_Y * 5.0)));
+        run(&mut app, 60);
+
+        let translation = app.world().get::<Transform>(body).unwrap().translation;
+        assert!(
+            translation.y < 2.4 - BODY_HALF_HEIGHT,
+            "body was stopped at {translation}"
+        );
+        assert!(app.world().get::<DropThrough>(body).is_none());
+    }
+
+    #[test]
+    fn reports_surface_material() {
+        let mut app = test_app();
+        let ground = spawn_floor(&mut app);
+        app.world_mut()
+            .entity_mut(ground)
+            .insert(SurfaceMaterial::ICE);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+
+        run(&mut app, 3);
+
+        let ground_info = app.world().get::<GroundInfo>(body).unwrap();
+        assert_eq!(ground_info.surface, SurfaceMaterial::ICE);
+    }
+
+    #[test]
+    fn marks_bodies_in_water_as_submerged() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let water = spawn_water(&mut app, Vec3::new(10.0, 1.0, 10.0), Vec3::Y);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+
+        run(&mut app, 3);
+
+        assert_eq!(
+            app.world().get::<CollisionLayers>(water),
+            Some(&CollisionLayers::new(CollisionLayer::Water, LayerMask::ALL))
+        );
+        assert!(app.world().get::<Sensor>(water).is_some());
+        let submerged = app.world().get::<Submerged>(body).unwrap();
+        assert_eq!(submerged.volume, water);
+        assert!((submerged.surface_height - 1.0).abs() < 0.05);
+        assert!((submerged.fraction - 1.0 / (2.0 * BODY_HALF_HEIGHT)).abs() < 0.05);
+        // the water doesn't get in the way of the body
+        assert!(app.world().get::<Grounded>(body).is_some());
+    }
+
+    #[test]
+    fn ignores_ground_while_climbing() {
+        let mut app = test_app();
+        spawn_floor(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+        run(&mut app, 3);
+        assert!(app.world().get::<Grounded>(body).is_some());
+
+        app.world_mut().entity_mut(body).insert(IgnoreGround);
+        run(&mut app, 3);
+
+        assert!(app.world().get::<Grounded>(body).is_none());
+        assert!(app.world().get::<GroundInfo>(body).is_none());
+        assert!(app.world().get::<LastGround>(body).is_some());
+    }
+
+    #[test]
+    fn measures_submersion_along_the_up_direction() {
+        let mut app = test_app();
+        spawn_water(&mut app, Vec3::splat(10.0), Vec3::Y * 5.0);
+        // a body standing on a wall, with its head sticking out of the side of the water
+        let body = spawn_body_at(&mut app, Vec3::X * 4.5, Vec3::ZERO);
+        app.world_mut()
+            .get_mut::<KinematicCharacterBody>(body)
+            .unwrap()
+            .up = Dir3::X;
+
+        run(&mut app, 3);
+
+        let submerged = app.world().get::<Submerged>(body).unwrap();
+        assert!((submerged.surface_height - 5.0).abs() < 0.05);
+        let expected_fraction = (5.0 - (4.5 - BODY_HALF_HEIGHT)) / (2.0 * BODY_HALF_HEIGHT);
+        assert!(
+            (submerged.fraction - expected_fraction).abs() < 0.05,
+            "unexpected fraction {}",
+            submerged.fraction
+        );
+    }
+
+    #[test]
+    fn dynamic_bodies_float_in_water() {
+        let mut app = test_app();
+        spawn_water(&mut app, Vec3::new(10.0, 4.0, 10.0), Vec3::ZERO);
+        let sphere = app
+            .world_mut()
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::sphere(0.5),
+                CollisionLayers::new(CollisionLayer::Prop, LayerMask::ALL),
+                Transform::from_xyz(0.0, -2.0, 0.0),
+            ))
+            .id();
+
+        run(&mut app, 600);
+
+        // gravity is balanced once 1 / buoyancy of the sphere's height is below the surface
+        let expected_height = 0.5 - 1.0 / WaterVolume::default().buoyancy;
+        let height = app.world().get::<Transform>(sphere).unwrap().translation.y;
+        assert!(
+            (height - expected_height).abs() < 0.1,
+            "sphere floats at {height}"
+        );
+    }
+
+    #[test]
+    fn falls_without_ground() {
+        let mut app = test_app();
+        add_gravity(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+
+        run(&mut app, 30);
+
+        let expected_velocity = -GRAVITY * 30.0 * TIMESTEP;
+        let velocity = velocity(&app, body);
+        assert!(
+            (velocity.y - expected_velocity).abs() < GRAVITY * TIMESTEP,
+            "unexpected velocity {velocity}"
+        );
+        assert!(translation(&app, body).y < 0.0);
+        assert!(!is_grounded(&app, body));
+    }
+
+    #[test]
+    fn comes_to_rest_on_the_floor() {
+        // (start, velocity, wall center, resting translation)
+        let cases = [
+            // dropped onto the floor
+            (Vec3::Y * 3.0, Vec3::ZERO, None, Vec3::Y * BODY_HALF_HEIGHT),
+            // walking into a wall
+            (
+                Vec3::Y * (BODY_HALF_HEIGHT + 0.01),
+                Vec3::X * 3.0,
+                Some(Vec3::new(2.0, 0.0, 0.0)),
+                Vec3::new(1.5 - 0.3, BODY_HALF_HEIGHT, 0.0),
+            ),
+        ];
+
+        for (start, start_velocity, wall, expected) in cases {
+            let mut app = test_app();
+            add_gravity(&mut app);
+            spawn_floor(&mut app);
+            if let Some(center) = wall {
+                spawn_wall(&mut app, Vec3::new(1.0, 4.0, 40.0), center);
+            }
+            let body = spawn_body_at(&mut app, start, start_velocity);
+
+            run(&mut app, 120);
+
+            let translation = translation(&app, body);
+            assert!(
+                (translation.x - expected.x).abs() < 0.01
+                    && (translation.y - expected.y).abs() < 0.02,
+                "body starting at {start} came to rest at {translation}"
+            );
+            assert!(is_grounded(&app, body));
+            assert!(velocity(&app, body).y.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn slides_down_slopes_only_when_they_are_too_steep() {
+        // (slope angle in degrees, whether the body slides down)
+        let cases = [(30f32, false), (60f32, true)];
+
+        for (angle, slides) in cases {
+            let mut app = test_app();
+            add_gravity(&mut app);
+            let (_, start) = spawn_slope(&mut app, angle.to_radians());
+            let body = spawn_body_at(&mut app, start, Vec3::ZERO);
+
+            run(&mut app, 30);
+
+            assert_eq!(is_grounded(&app, body), !slides, "{angle} degree slope");
+            assert_eq!(
+                app.world().get::<Sliding>(body).is_some(),
+                slides,
+                "{angle} degree slope"
+            );
+            let translation = translation(&app, body);
+            if slides {
+                assert!(
+                    translation.x < start.x - 0.3,
+                    "body didn't slide: {translation}"
+                );
+                assert!(translation.y < start.y);
+            } else {
+                assert!(
+                    translation.distance(start) < 0.05,
+                    "body moved on a walkable slope: {translation}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_and_lands_again() {
+        let mut app = test_app();
+        add_gravity(&mut app);
+        spawn_floor(&mut app);
+        let body = spawn_body(&mut app, Vec3::ZERO);
+        run(&mut app, 5);
+        assert!(is_grounded(&app, body));
+        let start = translation(&app, body);
+
+        let jump_speed = 5.0;
+        app.world_mut().get_mut::<Velocity>(body).unwrap().0 = Vec3::Y * jump_speed;
+        let mut max_height = f32::MIN;
+        for _ in 0..120 {
+            app.update();
+            max_height = max_height.max(translation(&app, body).y - start.y);
+        }
+
+        let expected_height = jump_speed * jump_speed / (2.0 * GRAVITY);
+        assert!(
+            (max_height - expected_height).abs() < 0.1,
+            "jumped {max_height} high"
+        );
+        assert!(is_grounded(&app, body));
+        assert!((translation(&app, body).y - start.y).abs() < 0.02);
+    }
+
+    #[test]
+    fn walks_over_generated_hills() {
+        let mut app = test_app();
+        add_gravity(&mut app);
+        let height = |x: f32, z: f32| 0.15 * (x.cos() + z.cos());
+        spawn_heightfield(&mut app, 20.0, 81, height);
+        let body = spawn_body_at(
+            &mut app,
+            Vec3::Y * (height(0.0, 0.0) + BODY_HALF_HEIGHT + 0.05),
+            Vec3::X * 3.0,
+        );
+
+        for _ in 0..90 {
+            // keep walking like the player would, as running up hills takes away speed
+            app.world_mut().get_mut::<Velocity>(body).unwrap().x = 3.0;
+            app.update();
+            let translation = translation(&app, body);
+            let ground_height = height(translation.x, translation.z);
+            assert!(
+                translation.y - BODY_HALF_HEIGHT > ground_height - 0.05,
+                "body sank into the ground at {translation}"
+            );
+        }
+
+        assert!(is_grounded(&app, body));
+        assert!(translation(&app, body).x > 3.5);
     }
 }
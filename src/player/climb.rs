@@ -0,0 +1,216 @@
+use super::{
+    input,
+    ledge::mount_ledge,
+    types::{Climbing, JumpState, Player},
+    FreeMovement, Jumped, PlayerCamera,
+};
+use crate::{
+    orbit_camera::TargetOf,
+    physics::{
+        adjusted_collider, Climbable, CollisionLayer, DynamicBodies, IgnoreGround,
+        KinematicCharacterBody, Velocity,
+    },
+};
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+
+// distance below the player, in which ground ends climbing down
+const GROUND_DETECTION_DISTANCE: f32 = 0.05;
+
+/// Looks for a [`Climbable`] volume on the [`CollisionLayer::Trigger`] layer the player overlaps.
+/// Returns its collider and the direction pointing away from its surface in world space.
+fn find_climbable(
+    spatial_query: &SpatialQuery,
+    entity: Entity,
+    collider: &Collider,
+    transform: &Transform,
+    climbables: &Query<(&Climbable, &GlobalTransform)>,
+) -> Option<(Entity, Vec3)> {
+    spatial_query
+        .shape_intersections(
+            collider,
+            transform.translation,
+            transform.rotation,
+            &SpatialQueryFilter::from_mask(CollisionLayer::Trigger)
+                .with_excluded_entities([entity]),
+        )
+        .into_iter()
+        .find_map(|volume| {
+            let (climbable, global_transform) = climbables.get(volume).ok()?;
+            Some((volume, global_transform.rotation() * *climbable.normal))
+        })
+}
+
+/// Starts climbing, when the player overlaps a [`Climbable`] volume and moves towards its surface.
+pub(super) fn start_climbing(
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &TargetOf,
+            &Collider,
+            &Transform,
+            &mut Velocity,
+        ),
+        FreeMovement,
+    >,
+    camera: PlayerCamera,
+    climbables: Query<(&Climbable, &GlobalTransform)>,
+    spatial_query: SpatialQuery,
+) {
+    let (entity, body, actions, target_of, collider, transform, mut velocity) = player.into_inner();
+    let Some(move_direction) = camera.move_direction(actions, body.up, target_of) else {
+        return;
+    };
+    let Some((volume, normal)) =
+        find_climbable(&spatial_query, entity, collider, transform, &climbables)
+    else {
+        return;
+    };
+    if move_direction.dot(normal) > -0.5 {
+        return;
+    }
+
+    velocity.0 = Vec3::ZERO;
+    commands
+        .entity(entity)
+        .insert((Climbing { volume, normal }, IgnoreGround));
+}
+
+/// Moves climbing players along the climbable surface. Climbing up to the top mounts the ledge
+/// above, climbing down onto the ground or out of the volume lets go and jumping jumps off of the
+/// surface with [`Player::climb_jump_impulse`].
+pub(super) fn climb(
+    mut commands: Commands,
+    player: Single<(
+        Entity,
+        &Player,
+        &KinematicCharacterBody,
+        &Actions<Player>,
+        &Collider,
+        &mut Transform,
+        &mut Velocity,
+        &mut JumpState,
+        &mut Climbing,
+    )>,
+    climbables: Query<(&Climbable, &GlobalTransform)>,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+    mut jumped: EventWriter<Jumped>,
+    time: Res<Time>,
+) {
+    let (
+        entity,
+        player,
+        body,
+        actions,
+        collider,
+        mut transform,
+        mut velocity,
+        mut jump_state,
+        mut climbing,
+    ) = player.into_inner();
+    velocity.0 = Vec3::ZERO;
+
+    let now = time.elapsed_secs();
+    let jump_requested = jump_state
+        .requested_at
+        .is_some_and(|requested_at| now - requested_at <= player.jump_buffer_time);
+    if jump_requested {
+        jump_state.requested_at = None;
+        jump_state.jumped_at = Some(now);
+        velocity.0 = climbing.normal * player.climb_jump_impulse;
+        commands.entity(entity).remove::<(Climbing, IgnoreGround)>();
+        jumped.write(Jumped {
+            entity,
+            air_jump_index: None,
+            wall: None,
+        });
+        return;
+    }
+
+    let Some((volume, normal)) =
+        find_climbable(&spatial_query, entity, collider, &transform, &climbables)
+    else {
+        commands.entity(entity).remove::<(Climbing, IgnoreGround)>();
+        return;
+    };
+    climbing.volume = volume;
+    climbing.normal = normal;
+
+    let (Ok(towards_surface), Ok(surface_up)) = (
+        Dir3::new(-normal.reject_from_normalized(*body.up)),
+        Dir3::new(body.up.reject_from_normalized(normal)),
+    ) else {
+        return;
+    };
+    transform.look_to(towards_surface, body.up);
+
+    let input = actions
+        .action::<input::Move>()
+        .value()
+        .as_axis2d()
+        .clamp_length_max(1.0);
+    let surface_right = towards_surface.cross(*surface_up);
+    velocity.0 = (surface_up * input.y + surface_right * input.x) * player.climb_speed;
+
+    if input.y > 0.0 {
+        if let Some(ledge_grab) = mount_ledge(
+            &spatial_query,
+            &dynamic_bodies,
+            entity,
+            player,
+            body,
+            collider,
+            &mut transform,
+            towards_surface,
+        ) {
+            velocity.0 = Vec3::ZERO;
+            commands
+                .entity(entity)
+                .remove::<(Climbing, IgnoreGround)>()
+                .insert(ledge_grab);
+        }
+    } else if input.y < 0.0
+        && is_on_ground(
+            &spatial_query,
+            &dynamic_bodies,
+            entity,
+            body,
+            collider,
+            &transform,
+        )
+    {
+        commands.entity(entity).remove::<(Climbing, IgnoreGround)>();
+    }
+}
+
+fn is_on_ground(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    body: &KinematicCharacterBody,
+    collider: &Collider,
+    transform: &Transform,
+) -> bool {
+    let Some(adjusted_collider) = adjusted_collider(collider) else {
+        return false;
+    };
+    spatial_query
+        .cast_shape_predicate(
+            &adjusted_collider,
+            transform.translation,
+            transform.rotation,
+            -body.up,
+            &ShapeCastConfig {
+                max_distance: GROUND_DETECTION_DISTANCE,
+                ..Default::default()
+            },
+            &body.filter(entity),
+            &|collider| body.collides_with(collider, dynamic_bodies),
+        )
+        .is_some_and(|hit| body.is_walkable(hit.normal1))
+}
@@ -0,0 +1,120 @@
+use super::{
+    input::Dash,
+    types::{DashCollision, DashState, Dashing, Player},
+    FreeMovement, PlayerCamera,
+};
+use crate::{
+    orbit_camera::TargetOf,
+    physics::{CharacterCollisions, Grounded, KinematicCharacterBody, Velocity},
+};
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+
+pub(super) fn request_dash(
+    trigger: Trigger<Started<Dash>>,
+    mut players: Query<&mut DashState, FreeMovement>,
+) {
+    if let Ok(mut dash_state) = players.get_mut(trigger.target()) {
+        dash_state.requested = true;
+    }
+}
+
+/// Starts a requested dash in the input direction, or the direction the player is facing without
+/// input. Dashing uses up one of the [`Player::dash_charges`], which are given back on the ground,
+/// and is only possible [`Player::dash_cooldown`] seconds after the last dash ended.
+pub(super) fn start_dash(
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &Player,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &TargetOf,
+            &Transform,
+            &mut DashState,
+            Has<Grounded>,
+        ),
+        FreeMovement,
+    >,
+    camera: PlayerCamera,
+    time: Res<Time>,
+) {
+    let (entity, player, body, actions, target_of, transform, mut dash_state, is_grounded) =
+        player.into_inner();
+    if is_grounded {
+        dash_state.charges_used = 0;
+    }
+    let requested = std::mem::take(&mut dash_state.requested);
+    if !requested {
+        return;
+    }
+
+    let cooling_down = dash_state
+        .ended_at
+        .is_some_and(|ended_at| time.elapsed_secs() - ended_at < player.dash_cooldown);
+    if cooling_down || dash_state.charges_used >= player.dash_charges {
+        return;
+    }
+
+    let Some(direction) = camera
+        .move_direction(actions, body.up, target_of)
+        .or_else(|| Dir3::new(transform.forward().reject_from_normalized(*body.up)).ok())
+    else {
+        return;
+    };
+
+    dash_state.charges_used += 1;
+    commands.entity(entity).insert(Dashing {
+        direction,
+        remaining: player.dash_duration,
+    });
+}
+
+/// Moves dashing players at [`Player::dash_speed`], until the dash is over. Running into something
+/// ends the dash or turns it along the surface, depending on [`Player::dash_collision`].
+pub(super) fn dash(
+    mut commands: Commands,
+    player: Single<(
+        Entity,
+        &Player,
+        &KinematicCharacterBody,
+        &CharacterCollisions,
+        &mut Velocity,
+        &mut DashState,
+        &mut Dashing,
+    )>,
+    time: Res<Time>,
+) {
+    let (entity, player, body, collisions, mut velocity, mut dash_state, mut dashing) =
+        player.into_inner();
+
+    // collisions of the last physics update, walkable ground doesn't get in the way
+    for collision in &collisions.0 {
+        let normal = collision.normal;
+        if normal.dot(*dashing.direction) >= 0.0 || body.is_walkable(normal) {
+            continue;
+        }
+        let slide_direction = Dir3::new(
+            dashing
+                .direction
+                .reject_from_normalized(normal)
+                .reject_from_normalized(*body.up),
+        );
+        match (player.dash_collision, slide_direction) {
+            (DashCollision::Slide, Ok(slide_direction)) => dashing.direction = slide_direction,
+            _ => dashing.remaining = 0.0,
+        }
+    }
+
+    dashing.remaining -= time.delta_secs();
+    if dashing.remaining <= 0.0 {
+        // the momentum of the dash isn't kept
+        velocity.0 = velocity.clamp_length_max(player.max_speed);
+        dash_state.ended_at = Some(time.elapsed_secs());
+        commands.entity(entity).remove::<Dashing>();
+        return;
+    }
+
+    velocity.0 = dashing.direction * player.dash_speed;
+}
@@ -14,6 +14,14 @@ pub(super) struct Sprint;
 #[input_action(output = bool)]
 pub(super) struct Jump;
 
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub(super) struct Crouch;
+
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub(super) struct Dash;
+
 pub(super) fn binding(trigger: Trigger<Binding<Player>>, mut players: Query<&mut Actions<Player>>) {
     let mut actions = players.get_mut(trigger.target()).unwrap();
     actions
@@ -27,6 +35,13 @@ pub(super) fn binding(trigger: Trigger<Binding<Player>>, mut players: Query<&mut
 
     actions
         .bind::<Jump>()
-        .to((KeyCode::Space, GamepadButton::East))
-        .with_conditions(JustPress::default());
+        .to((KeyCode::Space, GamepadButton::East));
+
+    actions
+        .bind::<Crouch>()
+        .to((KeyCode::ControlLeft, GamepadButton::West));
+
+    actions
+        .bind::<Dash>()
+        .to((KeyCode::KeyE, GamepadButton::North));
 }
@@ -0,0 +1,395 @@
+use super::{
+    types::{JumpState, LedgeGrab, Player},
+    FreeMovement, PlayerCamera,
+};
+use crate::{
+    orbit_camera::TargetOf,
+    physics::{
+        adjusted_collider, distance_from_center_to_hull, DynamicBodies, Grounded,
+        KinematicCharacterBody, Sliding, Velocity,
+    },
+};
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+
+// distance the grab height is kept below the top of the ledge while hanging, so the ledge can still
+// be found in front of the player
+const HANG_DEPTH: f32 = 0.1;
+// distance behind the wall, at which the top of a ledge is looked for
+const LEDGE_PROBE_DEPTH: f32 = 0.1;
+// gap between the collider and the ledge, while pulling up onto it
+const MANTLE_CLEARANCE: f32 = 0.02;
+
+struct Ledge {
+    /// the collider of the ledge
+    entity: Entity,
+    wall_normal: Vec3,
+    /// point on the wall below the ledge, at the grab height
+    wall_point: Vec3,
+    /// height of the top of the ledge above the grab height
+    height: f32,
+}
+
+impl Ledge {
+    /// Offset from the player's position to the position hanging from the ledge.
+    fn hang_offset(&self, up: Dir3) -> Vec3 {
+        up * (self.height - HANG_DEPTH)
+    }
+
+    /// Moves the player at `position` to hang from the ledge.
+    fn grab(&self, up: Dir3, position: &mut Vec3) -> LedgeGrab {
+        let offset = self.hang_offset(up);
+        *position += offset;
+        LedgeGrab {
+            ledge: self.entity,
+            wall_normal: self.wall_normal,
+            wall_point: self.wall_point + offset,
+            mantle_target: None,
+        }
+    }
+}
+
+/// Looks for a ledge in `direction`, whose top is at most [`Player::ledge_search_height`] above the
+/// grab height of the player at `position`.
+fn find_ledge(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    player: &Player,
+    body: &KinematicCharacterBody,
+    position: Vec3,
+    direction: Dir3,
+) -> Option<Ledge> {
+    let filter = body.filter(entity);
+    let predicate = |collider| body.collides_with(collider, dynamic_bodies);
+    let grab_point = position + body.up * player.ledge_grab_height;
+    let wall_hit = spatial_query.cast_ray_predicate(
+        grab_point,
+        direction,
+        player.radius + player.ledge_grab_reach,
+        true,
+        &filter,
+        &predicate,
+    )?;
+    if wall_hit.normal.dot(*body.up).abs() > player.max_wall_angle.sin() {
+        return None;
+    }
+
+    let wall_point = grab_point + direction * wall_hit.distance;
+    let top_hit = spatial_query.cast_ray_predicate(
+        wall_point - wall_hit.normal * LEDGE_PROBE_DEPTH + body.up * player.ledge_search_height,
+        -body.up,
+        player.ledge_search_height,
+        true,
+        &filter,
+        &predicate,
+    )?;
+    // the ray starts inside of walls that are too high to grab onto
+    if top_hit.distance <= 0.0 || !body.is_walkable(top_hit.normal) {
+        return None;
+    }
+
+    Some(Ledge {
+        entity: wall_hit.entity,
+        wall_normal: wall_hit.normal,
+        wall_point,
+        height: player.ledge_search_height - top_hit.distance,
+    })
+}
+
+/// Whether the player's collider can move by `offset` without hitting anything, e.g. to hang from a
+/// ledge.
+fn can_move_by(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    body: &KinematicCharacterBody,
+    collider: &Collider,
+    transform: &Transform,
+    offset: Vec3,
+) -> bool {
+    let Some(collider) = adjusted_collider(collider) else {
+        return false;
+    };
+    let Ok((direction, distance)) = Dir3::new_and_length(offset) else {
+        return true;
+    };
+    spatial_query
+        .cast_shape_predicate(
+            &collider,
+            transform.translation,
+            transform.rotation,
+            direction,
+            &ShapeCastConfig {
+                max_distance: distance,
+                ..Default::default()
+            },
+            &body.filter(entity),
+            &|collider| body.collides_with(collider, dynamic_bodies),
+        )
+        .is_none()
+}
+
+/// Grabs ledges in front of falling players, that move towards them and fit into the hanging
+/// position.
+pub(super) fn grab_ledge(
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &Player,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &TargetOf,
+            &Collider,
+            &mut Transform,
+            &mut Velocity,
+        ),
+        (Without<Grounded>, Without<Sliding>, FreeMovement),
+    >,
+    camera: PlayerCamera,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+) {
+    let (entity, player, body, actions, target_of, collider, mut transform, mut velocity) =
+        player.into_inner();
+    if velocity.dot(*body.up) > 0.0 {
+        return;
+    }
+
+    let Some(direction) = camera.move_direction(actions, body.up, target_of) else {
+        return;
+    };
+    let Some(ledge) = find_ledge(
+        &spatial_query,
+        &dynamic_bodies,
+        entity,
+        player,
+        body,
+        transform.translation,
+        direction,
+    ) else {
+        return;
+    };
+    if !can_move_by(
+        &spatial_query,
+        &dynamic_bodies,
+        entity,
+        body,
+        collider,
+        &transform,
+        ledge.hang_offset(body.up),
+    ) {
+        return;
+    }
+
+    let ledge_grab = ledge.grab(body.up, &mut transform.translation);
+    velocity.0 = Vec3::ZERO;
+    commands.entity(entity).insert(ledge_grab);
+}
+
+/// Grabs the ledge in `direction` and starts pulling the player up onto it right away, e.g. at the
+/// top of a ladder. Returns `None` if there is no ledge, no room to hang from it or no room on top
+/// of it.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn mount_ledge(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    player: &Player,
+    body: &KinematicCharacterBody,
+    collider: &Collider,
+    transform: &mut Transform,
+    direction: Dir3,
+) -> Option<LedgeGrab> {
+    let ledge = find_ledge(
+        spatial_query,
+        dynamic_bodies,
+        entity,
+        player,
+        body,
+        transform.translation,
+        direction,
+    )?;
+    if !can_move_by(
+        spatial_query,
+        dynamic_bodies,
+        entity,
+        body,
+        collider,
+        transform,
+        ledge.hang_offset(body.up),
+    ) {
+        return None;
+    }
+    let mut hanging_transform = *transform;
+    let mut ledge_grab = ledge.grab(body.up, &mut hanging_transform.translation);
+    ledge_grab.mantle_target = Some(mantle_target(
+        spatial_query,
+        dynamic_bodies,
+        entity,
+        player,
+        body,
+        collider,
+        &hanging_transform,
+        &ledge_grab,
+    )?);
+    *transform = hanging_transform;
+    Some(ledge_grab)
+}
+
+/// Keeps players hanging from ledges in place. Moving sideways shimmies along the ledge, jumping
+/// pulls the player up onto it and moving away from it lets go.
+pub(super) fn hang_from_ledge(
+    mut commands: Commands,
+    player: Single<(
+        Entity,
+        &Player,
+        &KinematicCharacterBody,
+        &Actions<Player>,
+        &TargetOf,
+        &Collider,
+        &mut Transform,
+        &mut Velocity,
+        &mut JumpState,
+        &mut LedgeGrab,
+    )>,
+    camera: PlayerCamera,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+    time: Res<Time>,
+) {
+    let (
+        entity,
+        player,
+        body,
+        actions,
+        target_of,
+        collider,
+        mut transform,
+        mut velocity,
+        mut jump_state,
+        mut ledge_grab,
+    ) = player.into_inner();
+    velocity.0 = Vec3::ZERO;
+
+    if let Some(target) = ledge_grab.mantle_target {
+        // pull up first and move onto the ledge afterwards, so the collider doesn't hit the edge
+        let step = player.mantle_speed * time.delta_secs();
+        let offset = target - transform.translation;
+        if offset.length() <= step {
+            transform.translation = target;
+            commands.entity(entity).remove::<LedgeGrab>();
+            return;
+        }
+        let vertical_offset = offset.project_onto_normalized(*body.up);
+        let horizontal_offset = offset - vertical_offset;
+        transform.translation += if vertical_offset.length() > step {
+            vertical_offset.clamp_length_max(step)
+        } else {
+            vertical_offset + horizontal_offset.clamp_length_max(step - vertical_offset.length())
+        };
+        return;
+    }
+
+    let now = time.elapsed_secs();
+    let jump_requested = jump_state
+        .requested_at
+        .is_some_and(|requested_at| now - requested_at <= player.jump_buffer_time);
+    if jump_requested {
+        jump_state.requested_at = None;
+        ledge_grab.mantle_target = mantle_target(
+            &spatial_query,
+            &dynamic_bodies,
+            entity,
+            player,
+            body,
+            collider,
+            &transform,
+            &ledge_grab,
+        );
+        return;
+    }
+
+    let Some(move_direction) = camera.move_direction(actions, body.up, target_of) else {
+        return;
+    };
+    if move_direction.dot(ledge_grab.wall_normal) > 0.5 {
+        commands.entity(entity).remove::<LedgeGrab>();
+        return;
+    }
+
+    // only shimmy as long as there is a ledge to hold on to
+    let Ok(towards_wall) = Dir3::new(-ledge_grab.wall_normal.reject_from_normalized(*body.up))
+    else {
+        return;
+    };
+    let tangent = body.up.cross(*towards_wall);
+    let shimmy_velocity = tangent * move_direction.dot(tangent) * player.shimmy_speed;
+    if let Some(ledge) = find_ledge(
+        &spatial_query,
+        &dynamic_bodies,
+        entity,
+        player,
+        body,
+        transform.translation + shimmy_velocity * time.delta_secs(),
+        towards_wall,
+    ) {
+        velocity.0 = shimmy_velocity;
+        ledge_grab.ledge = ledge.entity;
+        ledge_grab.wall_normal = ledge.wall_normal;
+        ledge_grab.wall_point = ledge.wall_point;
+    }
+}
+
+/// Position on top of the ledge, that the player is pulled up to while mantling. Returns `None` if
+/// the way up or the top of the ledge is blocked.
+#[allow(clippy::too_many_arguments)]
+fn mantle_target(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    player: &Player,
+    body: &KinematicCharacterBody,
+    collider: &Collider,
+    transform: &Transform,
+    ledge_grab: &LedgeGrab,
+) -> Option<Vec3> {
+    let collider = adjusted_collider(collider)?;
+    let forward = Dir3::new(-ledge_grab.wall_normal.reject_from_normalized(*body.up)).ok()?;
+    let half_height = distance_from_center_to_hull(&collider, transform.rotation, -body.up)?;
+    let radius = distance_from_center_to_hull(&collider, transform.rotation, forward)?;
+
+    // raise the bottom of the collider above the top of the ledge, then move forward until the
+    // collider is completely on top of it
+    let rise = player.ledge_grab_height + HANG_DEPTH + half_height + MANTLE_CLEARANCE;
+    let forward_distance =
+        (ledge_grab.wall_point - transform.translation).dot(*forward) + radius + MANTLE_CLEARANCE;
+    let raised_position = transform.translation + body.up * rise;
+
+    let filter = body.filter(entity);
+    let is_blocked = |origin: Vec3, direction: Dir3, distance: f32| {
+        spatial_query
+            .cast_shape_predicate(
+                &collider,
+                origin,
+                transform.rotation,
+                direction,
+                &ShapeCastConfig {
+                    max_distance: distance,
+                    ..Default::default()
+                },
+                &filter,
+                &|collider| body.collides_with(collider, dynamic_bodies),
+            )
+            .is_some()
+    };
+    if is_blocked(transform.translation, body.up, rise)
+        || is_blocked(raised_position, forward, forward_distance)
+    {
+        return None;
+    }
+
+    Some(raised_position + forward * forward_distance)
+}
@@ -1,16 +1,42 @@
+mod climb;
+mod dash;
 mod input;
+mod ledge;
+mod swim;
 pub mod types;
 
 use crate::{
     orbit_camera::{OrbitCamera, PreventBlindness, Smoothing, TargetOf},
-    physics::{CollisionLayer, Grounded, KinematicCharacterBody, Velocity},
+    physics::{
+        adjusted_collider, CharacterSchedule, CharacterSet, CollisionLayer, DropThrough,
+        DynamicBodies, GroundInfo, Grounded, KinematicCharacterBody, LastGround, OneWayPlatforms,
+        Sliding, Velocity,
+    },
 };
 use avian3d::prelude::*;
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_enhanced_input::prelude::*;
+use climb::{climb, start_climbing};
+use dash::{dash, request_dash, start_dash};
 use input::*;
+use ledge::{grab_ledge, hang_from_ledge};
 use std::f32::consts::PI;
-use types::{Player, PlayerModel};
+use swim::{swim, update_swimming};
+use types::{
+    Climbing, Crouching, Dashing, JumpState, LedgeGrab, Player, PlayerModel, PlayerModelInstance,
+    Swimming, WallContact,
+};
+
+// distance in front of the player, in which walls are detected
+const WALL_DETECTION_DISTANCE: f32 = 0.1;
+
+/// Filter for the systems of the usual movement and of swimming. Hanging from a ledge, dashing and
+/// climbing replace both with their own movement.
+type NotOverridden = (Without<LedgeGrab>, Without<Dashing>, Without<Climbing>);
+
+/// Filter for the systems of the usual movement, including gravity and jumping. Swimming replaces it
+/// with its own movement as well.
+type FreeMovement = (NotOverridden, Without<Swimming>);
 
 // TODO: decouple movement logic from input logic
 
@@ -21,11 +47,37 @@ impl Plugin for PlayerPlugin {
         app.add_input_context::<Player>()
             .add_observer(binding)
             .add_observer(on_spawn_player)
-            .add_observer(jump)
+            .add_observer(request_jump)
+            .add_observer(reset_air_jumps)
+            .add_observer(request_dash)
+            .add_event::<Jumped>()
             .add_systems(Startup, setup)
             .add_systems(
-                Update,
-                (grounded_movement, airborne_movement, apply_gravity),
+                CharacterSchedule,
+                (
+                    stamp_jump_request
+                        .before(jump)
+                        .before(climb)
+                        .before(hang_from_ledge),
+                    crouch.before(jump),
+                    detect_wall.before(jump),
+                    jump.before(grounded_movement),
+                    cut_jump.after(jump),
+                    grounded_movement,
+                    airborne_movement,
+                    sliding_movement,
+                    apply_gravity,
+                    wall_slide.after(apply_gravity),
+                    grab_ledge.after(apply_gravity).after(airborne_movement),
+                    hang_from_ledge.after(grab_ledge),
+                    start_dash.after(jump),
+                    dash.after(start_dash),
+                    update_swimming.before(jump),
+                    swim.after(update_swimming).after(jump),
+                    start_climbing.before(jump),
+                    climb.after(start_climbing).before(hang_from_ledge),
+                )
+                    .in_set(CharacterSet::Movement),
             );
     }
 }
@@ -39,16 +91,33 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 #[derive(Event)]
 pub struct SpawnPlayer {
     pub transform: Transform,
+    /// character body of the spawned player
+    pub body: KinematicCharacterBody,
 }
 
+/// Sent when a player jumps.
+#[derive(Event, Debug)]
+pub struct Jumped {
+    pub entity: Entity,
+    /// index into [`Player::air_jumps`] for jumps in the air, `None` for other jumps
+    pub air_jump_index: Option<usize>,
+    /// the collider of the wall for wall jumps
+    pub wall: Option<Entity>,
+}
+
+/// Gives the targeted player all of its [`Player::air_jumps`] back, e.g. after collecting a pickup.
+#[derive(Event)]
+pub struct ResetAirJumps;
+
 fn on_spawn_player(
-    trigger: Trigger<SpawnPlayer>,
+    mut trigger: Trigger<SpawnPlayer>,
     player_model: Res<PlayerModel>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     let transform = trigger.event().transform;
+    let body = std::mem::take(&mut trigger.event_mut().body);
 
     // TODO: move to an appropriate spot
     let camera = commands
@@ -62,7 +131,9 @@ fn on_spawn_player(
         ))
         .id();
 
-    let mesh = meshes.add(Capsule3d::new(0.3, 1.3));
+    let player = Player::default();
+    let height = player.height;
+    let mesh = meshes.add(player.mesh(height));
     let material = StandardMaterial {
         base_color: Color::WHITE.with_alpha(0.5),
         alpha_mode: AlphaMode::Blend,
@@ -73,10 +144,10 @@ fn on_spawn_player(
     let player = commands
         .spawn((
             Name::new("Player"),
-            Player::default(),
+            player.collider(height),
+            player,
             Actions::<Player>::default(),
-            KinematicCharacterBody::default(),
-            Collider::capsule(0.3, 1.3),
+            body,
             CollisionLayers::new(CollisionLayer::Player, LayerMask::ALL),
             Mesh3d(mesh),
             MeshMaterial3d(material),
@@ -90,72 +161,138 @@ fn on_spawn_player(
         ))
         .id();
     commands.spawn((
+        PlayerModelInstance,
         SceneRoot(player_model.0.clone()),
         Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, PI))
-            .with_translation(Vec3::NEG_Y * (0.3 + 0.65)),
+            .with_translation(Vec3::NEG_Y * height * 0.5),
         ChildOf(player),
     ));
 }
 
+/// Shrinks the player's collider around its feet while crouching. Standing up is only possible, if
+/// there is enough headroom above the player. Crouching isn't possible while swimming, where the
+/// crouch input swims down, or while climbing and hanging from a ledge.
+fn crouch(
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &Player,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &mut Collider,
+            &mut Transform,
+            Option<&Children>,
+            Has<Crouching>,
+        ),
+        (Without<Swimming>, Without<LedgeGrab>, Without<Climbing>),
+    >,
+    mut player_meshes: Query<&mut Mesh3d, With<Player>>,
+    mut models: Query<&mut Transform, (With<PlayerModelInstance>, Without<Player>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+) {
+    let (entity, player, body, actions, mut collider, mut transform, children, is_crouching) =
+        player.into_inner();
+    let wants_to_crouch = actions.action::<Crouch>().state() == ActionState::Fired;
+    let height_difference = player.height - player.crouch_height;
+
+    let height = if wants_to_crouch && !is_crouching {
+        transform.translation -= body.up * height_difference * 0.5;
+        commands.entity(entity).insert(Crouching);
+        player.crouch_height
+    } else if !wants_to_crouch && is_crouching {
+        let Some(adjusted_collider) = adjusted_collider(&collider) else {
+            return;
+        };
+        let blocked = spatial_query
+            .cast_shape_predicate(
+                &adjusted_collider,
+                transform.translation,
+                transform.rotation,
+                body.up,
+                &ShapeCastConfig {
+                    max_distance: height_difference,
+                    ..Default::default()
+                },
+                &body.filter(entity),
+                &|collider| body.collides_with(collider, &dynamic_bodies),
+            )
+            .is_some();
+        if blocked {
+            return;
+        }
+
+        transform.translation += body.up * height_difference * 0.5;
+        commands.entity(entity).remove::<Crouching>();
+        player.height
+    } else {
+        return;
+    };
+
+    *collider = player.collider(height);
+    if let Ok(mut mesh) = player_meshes.get_mut(entity) {
+        mesh.0 = meshes.add(player.mesh(height));
+    }
+    for &child in children.into_iter().flatten() {
+        if let Ok(mut model_transform) = models.get_mut(child) {
+            model_transform.translation = Vec3::NEG_Y * height * 0.5;
+        }
+    }
+}
+
 fn grounded_movement(
     player: Single<
         (
             &Player,
+            &KinematicCharacterBody,
             &Actions<Player>,
             &TargetOf,
+            &GroundInfo,
             &mut Transform,
             &mut Velocity,
+            Has<Crouching>,
         ),
-        With<Grounded>,
+        (With<Grounded>, FreeMovement),
     >,
-    cameras: Query<&Transform, (With<OrbitCamera>, Without<Player>)>,
+    camera: PlayerCamera,
     time: Res<Time>,
 ) {
-    let (player, actions, target_of, mut transform, mut velocity) = player.into_inner();
-    let mut input_direction = actions
-        .action::<input::Move>()
-        .value()
-        .as_axis2d()
-        .normalize_or_zero();
-    input_direction.y = -input_direction.y;
-    if input_direction.length_squared() > 0.0 {
+    let (player, body, actions, target_of, ground, mut transform, mut velocity, is_crouching) =
+        player.into_inner();
+    let surface = ground.surface;
+    let vertical_velocity = velocity.project_onto_normalized(*body.up);
+    let horizontal_velocity = velocity.0 - vertical_velocity;
+    if let Some(move_direction) = camera.move_direction(actions, body.up, target_of) {
         // rotation
-        transform.look_to(
-            Dir3::new_unchecked(Vec3::new(input_direction.x, 0.0, input_direction.y)),
-            Dir3::new_unchecked(Vec3::Y),
-        );
-
-        // adjust rotation to take player camera rotation into account
-        if let Ok(player_camera_transform) = cameras.get(target_of.0) {
-            let (yaw, _, _) = player_camera_transform.rotation.to_euler(EulerRot::YXZ);
-            let camera_rotation_offset = Quat::from_axis_angle(Vec3::Y, yaw);
-            transform.rotate(camera_rotation_offset);
-            input_direction = Mat2::from_angle(-yaw) * input_direction;
-        }
+        transform.look_to(move_direction, body.up);
 
         // basic horizontal movement
         let mut acceleration = player.acceleration;
         let mut max_speed = player.max_speed;
-        if actions.action::<Sprint>().state() == ActionState::Fired {
+        if is_crouching {
+            max_speed = player.crouch_max_speed;
+        } else if actions.action::<Sprint>().state() == ActionState::Fired {
             acceleration = player.sprint_acceleration;
             max_speed = player.sprint_max_speed;
         }
 
+        acceleration *= surface.acceleration;
+        max_speed *= surface.max_speed;
+
         let target_speed =
-            (velocity.xz().length() + acceleration * time.delta_secs()).clamp(0.0, max_speed);
-        let target_velocity = input_direction * target_speed;
-        velocity.x = target_velocity.x;
-        velocity.z = target_velocity.y;
+            (horizontal_velocity.length() + acceleration * time.delta_secs()).clamp(0.0, max_speed);
+        velocity.0 = move_direction * target_speed + vertical_velocity;
     } else {
         // apply ground friction
-        let decelerated_speed =
-            velocity.xz().length() - player.grounded_deceleration * time.delta_secs();
-        let mut decelerated_velocity = Vec2::ZERO;
+        let decelerated_speed = horizontal_velocity.length()
+            - player.grounded_deceleration * surface.friction * time.delta_secs();
+        let mut decelerated_velocity = Vec3::ZERO;
         if decelerated_speed > 0.0 {
-            decelerated_velocity = velocity.xz().clamp_length_max(decelerated_speed);
+            decelerated_velocity = horizontal_velocity.clamp_length_max(decelerated_speed);
         }
-        velocity.x = decelerated_velocity.x;
-        velocity.z = decelerated_velocity.y;
+        velocity.0 = decelerated_velocity + vertical_velocity;
     }
 }
 
@@ -163,57 +300,841 @@ fn airborne_movement(
     player: Single<
         (
             &Player,
+            &KinematicCharacterBody,
             &Actions<Player>,
             &TargetOf,
             &mut Transform,
             &mut Velocity,
         ),
-        Without<Grounded>,
+        (Without<Grounded>, Without<Sliding>, FreeMovement),
     >,
-    cameras: Query<&Transform, (With<OrbitCamera>, Without<Player>)>,
+    camera: PlayerCamera,
     time: Res<Time>,
 ) {
-    let (player, actions, target_of, mut transform, mut velocity) = player.into_inner();
-    let mut input_direction = actions
-        .action::<input::Move>()
-        .value()
-        .as_axis2d()
-        .normalize_or_zero();
-    input_direction.y = -input_direction.y;
-    if input_direction.length_squared() > 0.0 {
+    let (player, body, actions, target_of, mut transform, mut velocity) = player.into_inner();
+    if let Some(move_direction) = camera.move_direction(actions, body.up, target_of) {
         // rotation
-        transform.look_to(
-            Dir3::new_unchecked(Vec3::new(input_direction.x, 0.0, input_direction.y)),
-            Dir3::new_unchecked(Vec3::Y),
-        );
+        transform.look_to(move_direction, body.up);
+
+        // basic horizontal movement, momentum above the maximum speed (e.g. from jumping off of a
+        // moving platform) is kept, but can't be increased further
+        let vertical_velocity = velocity.project_onto_normalized(*body.up);
+        let horizontal_velocity = velocity.0 - vertical_velocity;
+        let max_speed = player.max_speed.max(horizontal_velocity.length());
+        let target_velocity = (horizontal_velocity
+            + move_direction * player.airborne_acceleration * time.delta_secs())
+        .clamp_length_max(max_speed);
+        velocity.0 = target_velocity + vertical_velocity;
+    }
+}
+
+/// Slides the player down slopes that are too steep to stand on, while the input steers sideways
+/// and downhill. This is specific to the player, [`Sliding`] bodies without it only slide down
+/// through their own gravity.
+fn sliding_movement(
+    player: Single<
+        (
+            &Player,
+            &KinematicCharacterBody,
+            &Sliding,
+            &Actions<Player>,
+            &TargetOf,
+            &mut Transform,
+            &mut Velocity,
+        ),
+        FreeMovement,
+    >,
+    camera: PlayerCamera,
+    time: Res<Time>,
+) {
+    let (player, body, sliding, actions, target_of, mut transform, mut velocity) =
+        player.into_inner();
+    let normal = sliding.normal;
+
+    // velocity into the slope is removed, so gravity doesn't keep fighting collide_and_slide
+    velocity.0 -= normal * velocity.dot(normal).min(0.0);
+
+    // accelerate down the slope
+    let gravity = -body.up * player.gravity();
+    let downhill_acceleration = gravity.reject_from_normalized(normal);
+    velocity.0 += downhill_acceleration * time.delta_secs();
+
+    // apply friction, proportional to how hard gravity presses the body onto the slope
+    let friction = player.slope_friction * gravity.dot(normal).abs() * time.delta_secs();
+    let speed = velocity.length();
+    velocity.0 = velocity.clamp_length_max((speed - friction).max(0.0));
 
-        // adjust rotation to take player camera rotation into account
-        if let Ok(player_camera_transform) = cameras.get(target_of.0) {
-            let (yaw, _, _) = player_camera_transform.rotation.to_euler(EulerRot::YXZ);
-            let camera_rotation_offset = Quat::from_axis_angle(Vec3::Y, yaw);
-            transform.rotate(camera_rotation_offset);
-            input_direction = Mat2::from_angle(-yaw) * input_direction;
+    // steering is only allowed sideways and downhill, so it can't push the body up the slope. Like
+    // airborne movement, it only accelerates the body up to the maximum speed in either direction.
+    if let Some(move_direction) = camera.move_direction(actions, body.up, target_of) {
+        transform.look_to(move_direction, body.up);
+
+        let downhill = downhill_acceleration.normalize_or_zero();
+        let across = downhill.cross(normal).normalize_or_zero();
+        let acceleration = player.airborne_acceleration * time.delta_secs();
+
+        let across_speed = velocity.dot(across);
+        let max_across_speed = player.max_speed.max(across_speed.abs());
+        let target_across_speed = (across_speed + move_direction.dot(across) * acceleration)
+            .clamp(-max_across_speed, max_across_speed);
+        velocity.0 += across * (target_across_speed - across_speed);
+
+        let downhill_steering = (move_direction.dot(downhill) * acceleration)
+            .min(player.max_speed - velocity.dot(downhill))
+            .max(0.0);
+        velocity.0 += downhill * downhill_steering;
+    }
+}
+
+/// Looks up the camera following the player, which the move input is relative to.
+#[derive(SystemParam)]
+struct PlayerCamera<'w, 's> {
+    cameras: Query<'w, 's, &'static Transform, (With<OrbitCamera>, Without<Player>)>,
+}
+
+impl PlayerCamera<'_, '_> {
+    /// Rotation of the camera targeted by `target_of`, or the identity if there is none.
+    fn rotation(&self, target_of: &TargetOf) -> Quat {
+        self.cameras
+            .get(target_of.0)
+            .map_or(Quat::IDENTITY, |camera_transform| camera_transform.rotation)
+    }
+
+    /// Direction of the move input in `actions`, see [`move_direction`].
+    fn move_direction(
+        &self,
+        actions: &Actions<Player>,
+        up: Dir3,
+        target_of: &TargetOf,
+    ) -> Option<Dir3> {
+        let input = actions.action::<input::Move>().value().as_axis2d();
+        move_direction(input, up, self.rotation(target_of))
+    }
+}
+
+/// Maps the move input onto the plane perpendicular to `up`, relative to the orientation of the
+/// camera. Returns `None` if there is no input.
+fn move_direction(input: Vec2, up: Dir3, camera_rotation: Quat) -> Option<Dir3> {
+    let input = input.normalize_or_zero();
+    if input.length_squared() == 0.0 {
+        return None;
+    }
+
+    // while looking straight down, the camera's up direction points forward
+    let forward = (camera_rotation * Vec3::NEG_Z)
+        .reject_from_normalized(*up)
+        .try_normalize()
+        .or_else(|| {
+            (camera_rotation * Vec3::Y)
+                .reject_from_normalized(*up)
+                .try_normalize()
+        })?;
+    let right = forward.cross(*up);
+    Dir3::new(right * input.x + forward * input.y).ok()
+}
+
+fn request_jump(trigger: Trigger<Started<Jump>>, mut players: Query<&mut JumpState>) {
+    if let Ok(mut jump_state) = players.get_mut(trigger.target()) {
+        jump_state.requested = true;
+    }
+}
+
+/// Records the time of jump requests in the schedule the movement runs in, as the input is read
+/// outside of it and [`Time<Fixed>`] differs from the time the input arrived at.
+fn stamp_jump_request(mut players: Query<&mut JumpState>, time: Res<Time>) {
+    for mut jump_state in &mut players {
+        if std::mem::take(&mut jump_state.requested) {
+            jump_state.requested_at = Some(time.elapsed_secs());
         }
+    }
+}
 
-        // basic horizontal movement
-        let target_velocity = (velocity.0.xz()
-            + input_direction * player.airborne_acceleration * time.delta_secs())
-        .clamp_length_max(player.max_speed);
-        velocity.x = target_velocity.x;
-        velocity.z = target_velocity.y;
+fn reset_air_jumps(trigger: Trigger<ResetAirJumps>, mut players: Query<&mut JumpState>) {
+    if let Ok(mut jump_state) = players.get_mut(trigger.target()) {
+        jump_state.air_jumps_used = 0;
     }
 }
 
+/// Jumps, if jumping was requested less than [`Player::jump_buffer_time`] ago and the player is
+/// grounded or left the ground less than [`Player::coyote_time`] ago without jumping. Otherwise the
+/// player jumps off of the wall it is pushing into, or uses the next of the [`Player::air_jumps`],
+/// which also turns the player towards the input direction. Air jumps are only used, if the player
+/// doesn't land before the buffer time runs out. While crouching on a one-way platform, the player
+/// drops down through it instead.
 fn jump(
-    trigger: Trigger<Fired<Jump>>,
-    mut players: Query<(&Player, &mut Velocity), With<Grounded>>,
+    mut commands: Commands,
+    player: Single<
+        (
+            Entity,
+            &Player,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &TargetOf,
+            &Collider,
+            &Transform,
+            &mut JumpState,
+            &mut Velocity,
+            Option<&GroundInfo>,
+            Option<&LastGround>,
+            Option<&WallContact>,
+        ),
+        FreeMovement,
+    >,
+    camera: PlayerCamera,
+    one_way_platforms: OneWayPlatforms,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+    mut jumped: EventWriter<Jumped>,
+    time: Res<Time>,
 ) {
-    if let Ok((player, mut velocity)) = players.get_mut(trigger.target()) {
-        velocity.y += player.jump_impulse;
+    let (
+        entity,
+        player,
+        body,
+        actions,
+        target_of,
+        collider,
+        transform,
+        mut jump_state,
+        mut velocity,
+        ground,
+        last_ground,
+        wall_contact,
+    ) = player.into_inner();
+    let now = time.elapsed_secs();
+    if ground.is_some() {
+        jump_state.air_jumps_used = 0;
+        jump_state.last_wall = None;
+    }
+    let Some(requested_at) = jump_state.requested_at else {
+        return;
+    };
+    if now - requested_at > player.jump_buffer_time {
+        jump_state.requested_at = None;
+        return;
+    }
+
+    let coyote_time_left = last_ground.is_some_and(|last_ground| {
+        now - last_ground.left_at <= player.coyote_time
+            && jump_state
+                .jumped_at
+                .is_none_or(|jumped_at| jumped_at < last_ground.left_at)
+    });
+    let air_jump = if let Some(ground) = ground {
+        if actions.action::<Crouch>().state() == ActionState::Fired
+            && one_way_platforms.get(ground.entity).is_some()
+        {
+            commands.entity(entity).insert(DropThrough::default());
+            jump_state.requested_at = None;
+            return;
+        }
+        None
+    } else if coyote_time_left {
+        None
+    } else if let Some(wall_contact) = wall_contact
+        .filter(|wall_contact| player.wall_jumping && !jump_state.is_locked_out(wall_contact))
+    {
+        jump_state.requested_at = None;
+        jump_state.jumped_at = Some(now);
+        jump_state.rising = true;
+        jump_state.last_wall = Some((wall_contact.wall, wall_contact.normal));
+        let away = wall_contact
+            .normal
+            .reject_from_normalized(*body.up)
+            .normalize_or_zero();
+        velocity.0 = away * player.wall_jump_away_impulse + body.up * player.wall_jump_up_impulse;
+        jumped.write(Jumped {
+            entity,
+            air_jump_index: None,
+            wall: Some(wall_contact.wall),
+        });
+        return;
+    } else {
+        let Some(air_jump) = player.air_jumps.get(jump_state.air_jumps_used) else {
+            return;
+        };
+        // the jump is kept for the landing instead, if it is within the buffer time
+        let buffer_time_left = player.jump_buffer_time - (now - requested_at);
+        if lands_within(
+            &spatial_query,
+            &dynamic_bodies,
+            entity,
+            player,
+            body,
+            collider,
+            transform,
+            velocity.0,
+            buffer_time_left,
+        ) {
+            return;
+        }
+        Some(air_jump)
+    };
+
+    jump_state.requested_at = None;
+    jump_state.jumped_at = Some(now);
+    jump_state.rising = true;
+    // downward velocity is removed, so jumping during coyote time is as high as from the ground
+    let mut horizontal_velocity = velocity.reject_from_normalized(*body.up);
+    let mut impulse = player.jump_impulse();
+    let mut air_jump_index = None;
+    if let Some(air_jump) = air_jump {
+        if let Some(move_direction) = camera.move_direction(actions, body.up, target_of) {
+            let redirected_velocity = move_direction * horizontal_velocity.length();
+            horizontal_velocity = horizontal_velocity.lerp(redirected_velocity, air_jump.redirect);
+        }
+        impulse = air_jump.impulse(player.gravity());
+        air_jump_index = Some(jump_state.air_jumps_used);
+        jump_state.air_jumps_used += 1;
     }
+    velocity.0 = horizontal_velocity + body.up * impulse;
+    jumped.write(Jumped {
+        entity,
+        air_jump_index,
+        wall: None,
+    });
+}
+
+/// Whether the falling player reaches walkable ground within `time` seconds.
+#[allow(clippy::too_many_arguments)]
+fn lands_within(
+    spatial_query: &SpatialQuery,
+    dynamic_bodies: &DynamicBodies,
+    entity: Entity,
+    player: &Player,
+    body: &KinematicCharacterBody,
+    collider: &Collider,
+    transform: &Transform,
+    velocity: Vec3,
+    time: f32,
+) -> bool {
+    let fall_speed = -velocity.dot(*body.up);
+    let fall_distance = fall_speed * time + 0.5 * player.gravity() * time * time;
+    let Some(adjusted_collider) = adjusted_collider(collider) else {
+        return false;
+    };
+    fall_distance > 0.0
+        && spatial_query
+            .cast_shape_predicate(
+                &adjusted_collider,
+                transform.translation,
+                transform.rotation,
+                -body.up,
+                &ShapeCastConfig {
+                    max_distance: fall_distance,
+                    ..Default::default()
+                },
+                &body.filter(entity),
+                &|collider| body.collides_with(collider, dynamic_bodies),
+            )
+            .is_some_and(|hit| body.is_walkable(hit.normal1))
 }
 
-fn apply_gravity(player: Single<(&Player, &mut Velocity), Without<Grounded>>, time: Res<Time>) {
-    let (player, mut velocity) = player.into_inner();
-    velocity.y -= player.gravity * time.delta_secs();
+/// Looks for a wall in the input direction while the player is airborne, which the player can slide
+/// down on and jump off of.
+fn detect_wall(
+    mut commands: Commands,
+    player: Single<(
+        Entity,
+        &Player,
+        &KinematicCharacterBody,
+        &Actions<Player>,
+        &TargetOf,
+        &Collider,
+        &Transform,
+        &mut JumpState,
+        Option<&WallContact>,
+        Has<Grounded>,
+        Has<Sliding>,
+    )>,
+    free_movement: Query<(), FreeMovement>,
+    camera: PlayerCamera,
+    spatial_query: SpatialQuery,
+    dynamic_bodies: DynamicBodies,
+) {
+    let (
+        entity,
+        player,
+        body,
+        actions,
+        target_of,
+        collider,
+        transform,
+        mut jump_state,
+        previous_wall_contact,
+        is_grounded,
+        is_sliding,
+    ) = player.into_inner();
+    let hit = camera
+        .move_direction(actions, body.up, target_of)
+        .filter(|_| !is_grounded && !is_sliding && free_movement.contains(entity))
+        .and_then(|direction| {
+            spatial_query.cast_shape_predicate(
+                &adjusted_collider(collider)?,
+                transform.translation,
+                transform.rotation,
+                direction,
+                &ShapeCastConfig {
+                    max_distance: WALL_DETECTION_DISTANCE,
+                    ..Default::default()
+                },
+                &body.filter(entity),
+                &|collider| body.collides_with(collider, &dynamic_bodies),
+            )
+        });
+    let wall_contact = hit
+        .filter(|hit| hit.normal1.dot(*body.up).abs() <= player.max_wall_angle.sin())
+        .map(|hit| WallContact {
+            wall: hit.entity,
+            normal: hit.normal1,
+        });
+
+    match wall_contact {
+        Some(wall_contact) => {
+            let touched_new_wall = previous_wall_contact.is_none_or(|previous_wall_contact| {
+                previous_wall_contact.wall != wall_contact.wall
+            });
+            if touched_new_wall && player.wall_jumping && !jump_state.is_locked_out(&wall_contact) {
+                jump_state.air_jumps_used = 0;
+            }
+            commands.entity(entity).insert(wall_contact);
+        }
+        None if previous_wall_contact.is_some() => {
+            commands.entity(entity).remove::<WallContact>();
+        }
+        None => {}
+    }
+}
+
+/// Limits the fall speed of players pushing into a wall to [`Player::max_wall_slide_speed`].
+fn wall_slide(
+    player: Single<
+        (&Player, &KinematicCharacterBody, &mut Velocity),
+        (With<WallContact>, FreeMovement),
+    >,
+) {
+    let (player, body, mut velocity) = player.into_inner();
+    let fall_speed = -velocity.dot(*body.up);
+    if fall_speed > player.max_wall_slide_speed {
+        velocity.0 += body.up * (fall_speed - player.max_wall_slide_speed);
+    }
+}
+
+/// Cuts the upward velocity of a jump down to [`Player::min_jump_speed`], once the jump input is
+/// released.
+fn cut_jump(
+    player: Single<(
+        &Player,
+        &KinematicCharacterBody,
+        &Actions<Player>,
+        &mut JumpState,
+        &mut Velocity,
+    )>,
+) {
+    let (player, body, actions, mut jump_state, mut velocity) = player.into_inner();
+    if !jump_state.rising {
+        return;
+    }
+
+    let upward_speed = velocity.dot(*body.up);
+    if upward_speed <= 0.0 {
+        jump_state.rising = false;
+    } else if actions.action::<Jump>().state() != ActionState::Fired {
+        jump_state.rising = false;
+        let min_jump_speed = player.min_jump_speed();
+        if upward_speed > min_jump_speed {
+            velocity.0 -= body.up * (upward_speed - min_jump_speed);
+        }
+    }
+}
+
+fn apply_gravity(
+    player: Single<
+        (&Player, &KinematicCharacterBody, &mut Velocity),
+        (Without<Grounded>, Without<Sliding>, FreeMovement),
+    >,
+    time: Res<Time>,
+) {
+    let (player, body, mut velocity) = player.into_inner();
+    velocity.0 -= body.up * player.gravity() * time.delta_secs();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::types::{AirJump, DashCollision, DashState};
+    use super::*;
+    use crate::test_harness::*;
+    use bevy::input::InputPlugin;
+    use bevy_enhanced_input::EnhancedInputPlugin;
+
+    fn add_player(app: &mut App) {
+        app.add_plugins((InputPlugin, EnhancedInputPlugin, PlayerPlugin));
+    }
+
+    /// Spawns `player` at `translation`. Without an orbit camera, the move input is relative to the
+    /// world axes: forward is -z and right is +x.
+    fn spawn_player(app: &mut App, player: Player, translation: Vec3) -> Entity {
+        let camera = app.world_mut().spawn(Transform::default()).id();
+        app.world_mut()
+            .spawn((
+                player.collider(player.height),
+                player,
+                CollisionLayers::new(CollisionLayer::Player, LayerMask::ALL),
+                Transform::from_translation(translation),
+                TargetOf(camera),
+            ))
+            .id()
+    }
+
+    fn press(app: &mut App, key: KeyCode) {
+        app.world_mut()
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(key);
+    }
+
+    fn release(app: &mut App, key: KeyCode) {
+        app.world_mut()
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .release(key);
+    }
+
+    fn jump_state(app: &App, player: Entity) -> &JumpState {
+        app.world().get::<JumpState>(player).unwrap()
+    }
+
+    fn is_dashing(app: &App, player: Entity) -> bool {
+        app.world().get::<Dashing>(player).is_some()
+    }
+
+    /// Updates until `condition` holds, at most `frames` times. Returns whether it held.
+    fn run_until(app: &mut App, frames: usize, condition: impl Fn(&App) -> bool) -> bool {
+        for _ in 0..frames {
+            app.update();
+            if condition(app) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn jumps_during_coyote_time_after_walking_off_a_ledge() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_terrain(&mut app, Vec3::new(4.0, 1.0, 4.0), Vec3::NEG_Y * 0.5);
+        let player = spawn_player(
+            &mut app,
+            Player::default(),
+            Vec3::new(1.0, BODY_HALF_HEIGHT + 0.01, 0.0),
+        );
+
+        press(&mut app, KeyCode::KeyD);
+        assert!(
+            run_until(&mut app, 120, |app| app
+                .world()
+                .get::<LastGround>(player)
+                .is_some()),
+            "player didn't walk off of the ledge"
+        );
+        run(&mut app, 2);
+        assert!(velocity(&app, player).y < 0.0);
+
+        press(&mut app, KeyCode::Space);
+        app.update();
+
+        let jump_impulse = Player::default().jump_impulse();
+        let velocity = velocity(&app, player);
+        assert!(
+            velocity.y > jump_impulse * 0.9,
+            "player didn't jump: {velocity}"
+        );
+        assert_eq!(jump_state(&app, player).air_jumps_used, 0);
+    }
+
+    fn assert_buffers_jump_before_landing(player: Player) {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        let player = spawn_player(&mut app, player, Vec3::Y * (BODY_HALF_HEIGHT + 2.0));
+
+        assert!(run_until(&mut app, 120, |app| {
+            translation(app, player).y - BODY_HALF_HEIGHT < 0.3
+        }));
+        assert!(!is_grounded(&app, player));
+        press(&mut app, KeyCode::Space);
+        app.update();
+        assert!(velocity(&app, player).y <= 0.0, "player jumped in the air");
+
+        assert!(
+            run_until(&mut app, 6, |app| velocity(app, player).y > 0.0),
+            "buffered jump was dropped"
+        );
+        assert!(jump_state(&app, player).requested_at.is_none());
+        assert_eq!(jump_state(&app, player).air_jumps_used, 0);
+    }
+
+    #[test]
+    fn buffers_jumps_pressed_right_before_landing() {
+        assert_buffers_jump_before_landing(Player::default());
+    }
+
+    #[test]
+    fn buffers_jumps_before_landing_instead_of_air_jumping() {
+        assert_buffers_jump_before_landing(Player {
+            air_jumps: vec![AirJump {
+                height: 2.0,
+                redirect: 0.5,
+            }],
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn cant_jump_off_of_the_same_wall_twice() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        let wall = spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 10.0, 40.0),
+            Vec3::new(1.5, 0.0, 0.0),
+        );
+        let player = spawn_player(&mut app, Player::default(), Vec3::new(0.65, 4.0, 0.0));
+
+        press(&mut app, KeyCode::KeyD);
+        assert!(run_until(&mut app, 10, |app| app
+            .world()
+            .get::<WallContact>(player)
+            .is_some()));
+        press(&mut app, KeyCode::Space);
+        app.update();
+        release(&mut app, KeyCode::Space);
+        let jumped_at = jump_state(&app, player).jumped_at;
+        assert!(jumped_at.is_some(), "player didn't jump off of the wall");
+        assert_eq!(
+            jump_state(&app, player).last_wall.map(|(wall, _)| wall),
+            Some(wall)
+        );
+        assert!(velocity(&app, player).x < 0.0);
+
+        // holding the move input pushes the player back into the wall
+        assert!(run_until(&mut app, 30, |app| app
+            .world()
+            .get::<WallContact>(player)
+            .is_none()));
+        assert!(
+            run_until(&mut app, 120, |app| app
+                .world()
+                .get::<WallContact>(player)
+                .is_some()),
+            "player didn't get back to the wall"
+        );
+        press(&mut app, KeyCode::Space);
+        run(&mut app, 5);
+
+        assert_eq!(jump_state(&app, player).jumped_at, jumped_at);
+        assert!(!is_grounded(&app, player));
+    }
+
+    #[test]
+    fn grabs_ledges_and_mantles_onto_them() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        let ledge = spawn_wall(
+            &mut app,
+            Vec3::new(4.0, 2.0, 40.0),
+            Vec3::new(3.0, 0.0, 0.0),
+        );
+        let player = spawn_player(&mut app, Player::default(), Vec3::new(0.65, 2.0, 0.0));
+
+        press(&mut app, KeyCode::KeyD);
+        assert!(
+            run_until(&mut app, 60, |app| app
+                .world()
+                .get::<LedgeGrab>(player)
+                .is_some()),
+            "player didn't grab the ledge"
+        );
+        assert_eq!(app.world().get::<LedgeGrab>(player).unwrap().ledge, ledge);
+        let hanging_position = translation(&app, player);
+        run(&mut app, 10);
+        assert!(translation(&app, player).distance(hanging_position) < 1e-3);
+
+        release(&mut app, KeyCode::KeyD);
+        press(&mut app, KeyCode::Space);
+        assert!(
+            run_until(&mut app, 90, |app| app
+                .world()
+                .get::<LedgeGrab>(player)
+                .is_none()),
+            "player didn't finish mantling"
+        );
+        run(&mut app, 5);
+
+        let translation = translation(&app, player);
+        assert!(
+            translation.x > 1.0,
+            "player is not on the ledge: {translation}"
+        );
+        assert!((translation.y - (2.0 + BODY_HALF_HEIGHT)).abs() < 0.05);
+        assert!(is_grounded(&app, player));
+    }
+
+    #[test]
+    fn only_grabs_ledges_with_room_to_hang_from_them() {
+        // whether there is a ceiling above the player, which blocks pulling up to the hang position
+        for blocked in [false, true] {
+            let mut app = test_app();
+            add_player(&mut app);
+            spawn_floor(&mut app);
+            spawn_wall(
+                &mut app,
+                Vec3::new(4.0, 2.0, 40.0),
+                Vec3::new(3.0, 0.0, 0.0),
+            );
+            // the grab height starts below the top of the ledge, so grabbing it pulls the player up
+            let start = Vec3::new(0.65, 1.2, 0.0);
+            if blocked {
+                spawn_terrain(
+                    &mut app,
+                    Vec3::new(1.0, 1.0, 40.0),
+                    Vec3::new(0.5, start.y + BODY_HALF_HEIGHT + 0.1 + 0.5, 0.0),
+                );
+            }
+            let player = spawn_player(&mut app, Player::default(), start);
+
+            press(&mut app, KeyCode::KeyD);
+            let grabbed = run_until(&mut app, 60, |app| {
+                app.world().get::<LedgeGrab>(player).is_some()
+            });
+            assert_eq!(grabbed, !blocked, "blocked: {blocked}");
+        }
+    }
+
+    #[test]
+    fn dash_charges_are_given_back_on_the_ground() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        let player = spawn_player(&mut app, Player::default(), Vec3::Y * 10.0);
+
+        press(&mut app, KeyCode::KeyE);
+        app.update();
+        release(&mut app, KeyCode::KeyE);
+        assert!(is_dashing(&app, player));
+
+        // wait for the dash and its cooldown to end, while still falling
+        run(&mut app, 30);
+        assert!(!is_dashing(&app, player));
+        assert!(!is_grounded(&app, player));
+        press(&mut app, KeyCode::KeyE);
+        app.update();
+        release(&mut app, KeyCode::KeyE);
+        assert!(!is_dashing(&app, player), "dashed without a charge left");
+        assert_eq!(
+            app.world().get::<DashState>(player).unwrap().charges_used,
+            1
+        );
+
+        assert!(run_until(&mut app, 120, |app| is_grounded(app, player)));
+        press(&mut app, KeyCode::KeyE);
+        app.update();
+        assert!(is_dashing(&app, player), "dash charge wasn't given back");
+    }
+
+    #[test]
+    fn dash_stops_at_walls_with_dash_collision_stop() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        spawn_wall(
+            &mut app,
+            Vec3::new(1.0, 4.0, 40.0),
+            Vec3::new(1.5, 0.0, 0.0),
+        );
+        let player = spawn_player(
+            &mut app,
+            Player {
+                dash_duration: 1.0,
+                dash_collision: DashCollision::Stop,
+                ..Default::default()
+            },
+            Vec3::Y * (BODY_HALF_HEIGHT + 0.01),
+        );
+        run(&mut app, 5);
+
+        // dash diagonally into the wall, which would slide along it with `DashCollision::Slide`
+        press(&mut app, KeyCode::KeyD);
+        press(&mut app, KeyCode::KeyW);
+        press(&mut app, KeyCode::KeyE);
+        app.update();
+        assert!(is_dashing(&app, player));
+
+        assert!(
+            run_until(&mut app, 30, |app| !is_dashing(app, player)),
+            "dash didn't stop at the wall"
+        );
+        assert!(app
+            .world()
+            .get::<DashState>(player)
+            .unwrap()
+            .ended_at
+            .is_some());
+    }
+
+    #[test]
+    fn crouches_and_only_stands_up_with_enough_headroom() {
+        let mut app = test_app();
+        add_player(&mut app);
+        spawn_floor(&mut app);
+        let player = spawn_player(
+            &mut app,
+            Player::default(),
+            Vec3::Y * (BODY_HALF_HEIGHT + 0.01),
+        );
+        run(&mut app, 5);
+
+        let crouch_half_height = Player::default().crouch_height * 0.5;
+        press(&mut app, KeyCode::ControlLeft);
+        run(&mut app, 5);
+        assert!(app.world().get::<Crouching>(player).is_some());
+        assert!((translation(&app, player).y - crouch_half_height).abs() < 0.05);
+
+        // a ceiling with enough room for crouching, but not for standing
+        let ceiling = spawn_terrain(
+            &mut app,
+            Vec3::new(40.0, 1.0, 40.0),
+            Vec3::Y * (crouch_half_height * 2.0 + 0.4 + 0.5),
+        );
+        release(&mut app, KeyCode::ControlLeft);
+        run(&mut app, 5);
+        assert!(
+            app.world().get::<Crouching>(player).is_some(),
+            "player stood up into the ceiling"
+        );
+
+        app.world_mut().despawn(ceiling);
+        run(&mut app, 5);
+        assert!(app.world().get::<Crouching>(player).is_none());
+        assert!((translation(&app, player).y - BODY_HALF_HEIGHT).abs() < 0.05);
+    }
+
+    #[test]
+    fn cant_steer_up_slopes_that_are_too_steep() {
+        let mut app = test_app();
+        add_player(&mut app);
+        let (_, start) = spawn_slope(&mut app, 60f32.to_radians());
+        let player = spawn_player(&mut app, Player::default(), start);
+
+        // the slope rises towards +x
+        press(&mut app, KeyCode::KeyD);
+        run(&mut app, 60);
+
+        let translation = translation(&app, player);
+        assert!(
+            translation.x < start.x && translation.y < start.y,
+            "player steered up the slope: {translation}"
+        );
+    }
 }
@@ -0,0 +1,111 @@
+use super::{
+    input::{self, Crouch, Jump},
+    types::{JumpState, Player, Swimming},
+    Jumped, NotOverridden, PlayerCamera,
+};
+use crate::{
+    orbit_camera::TargetOf,
+    physics::{Grounded, KinematicCharacterBody, Submerged, Velocity},
+};
+use bevy::prelude::*;
+use bevy_enhanced_input::prelude::*;
+
+/// Starts swimming once more than [`Player::swim_depth`] of the player is below the water surface.
+/// Swimming stops after leaving the water, or when standing on the ground in shallow water.
+pub(super) fn update_swimming(
+    mut commands: Commands,
+    player: Single<(
+        Entity,
+        &Player,
+        Option<&Submerged>,
+        Has<Grounded>,
+        Has<Swimming>,
+    )>,
+) {
+    let (entity, player, submerged, is_grounded, is_swimming) = player.into_inner();
+    let fraction = submerged.map_or(0.0, |submerged| submerged.fraction);
+    let should_swim =
+        fraction >= player.swim_depth || (is_swimming && !is_grounded && fraction > 0.0);
+    if should_swim && !is_swimming {
+        commands.entity(entity).insert(Swimming);
+    } else if !should_swim && is_swimming {
+        commands.entity(entity).remove::<Swimming>();
+    }
+}
+
+/// Moves swimming players in the direction the camera is looking, while buoyancy pushes them up to
+/// the surface and drag slows them down. Holding jump swims up and crouching swims down. Jumping
+/// while the head is above the water jumps out of it.
+pub(super) fn swim(
+    player: Single<
+        (
+            Entity,
+            &Player,
+            &KinematicCharacterBody,
+            &Actions<Player>,
+            &TargetOf,
+            &Submerged,
+            &mut Transform,
+            &mut Velocity,
+            &mut JumpState,
+        ),
+        (With<Swimming>, NotOverridden),
+    >,
+    camera: PlayerCamera,
+    mut jumped: EventWriter<Jumped>,
+    time: Res<Time>,
+) {
+    let (
+        entity,
+        player,
+        body,
+        actions,
+        target_of,
+        submerged,
+        mut transform,
+        mut velocity,
+        mut jump_state,
+    ) = player.into_inner();
+    let now = time.elapsed_secs();
+    let jump_requested = jump_state
+        .requested_at
+        .is_some_and(|requested_at| now - requested_at <= player.jump_buffer_time);
+    if jump_requested && submerged.fraction < 1.0 {
+        jump_state.requested_at = None;
+        jump_state.jumped_at = Some(now);
+        velocity.0 = velocity.reject_from_normalized(*body.up) + body.up * player.swim_jump_impulse;
+        jumped.write(Jumped {
+            entity,
+            air_jump_index: None,
+            wall: None,
+        });
+        return;
+    }
+
+    // buoyancy balances gravity, once enough of the player is below the surface
+    let water = submerged.water;
+    velocity.0 += body.up
+        * player.gravity()
+        * (water.buoyancy * submerged.fraction - 1.0)
+        * time.delta_secs();
+
+    let input = actions
+        .action::<input::Move>()
+        .value()
+        .as_axis2d()
+        .clamp_length_max(1.0);
+    let mut swim_direction = camera.rotation(target_of) * Vec3::new(input.x, 0.0, -input.y);
+    if actions.action::<Jump>().state() == ActionState::Fired {
+        swim_direction += *body.up;
+    }
+    if actions.action::<Crouch>().state() == ActionState::Fired {
+        swim_direction -= *body.up;
+    }
+    let swim_direction = swim_direction.clamp_length_max(1.0);
+    velocity.0 += swim_direction * player.swim_acceleration * time.delta_secs();
+    if let Ok(facing) = Dir3::new(swim_direction.reject_from_normalized(*body.up)) {
+        transform.look_to(facing, body.up);
+    }
+
+    velocity.0 *= (1.0 - water.drag * time.delta_secs()).max(0.0);
+}
@@ -1,34 +1,264 @@
 use crate::physics::KinematicCharacterBody;
+use avian3d::prelude::*;
 use bevy::prelude::*;
 use bevy_enhanced_input::prelude::*;
 
 #[derive(Component, InputContext)]
-#[require(KinematicCharacterBody, Actions<Player>)]
+#[require(KinematicCharacterBody, Actions<Player>, JumpState, DashState)]
 pub struct Player {
-    pub gravity: f32,
     pub acceleration: f32,
     pub max_speed: f32,
     pub sprint_acceleration: f32,
     pub sprint_max_speed: f32,
     pub grounded_deceleration: f32,
-    pub jump_impulse: f32,
+    /// height of a jump in metres, while holding the jump input
+    pub max_jump_height: f32,
+    /// height of a jump in metres, when the jump input is released right away
+    pub min_jump_height: f32,
+    /// seconds it takes to reach [`Player::max_jump_height`]
+    pub jump_time_to_apex: f32,
+    /// jumps the player can do in the air, in this order, before landing again
+    pub air_jumps: Vec<AirJump>,
+    /// whether the player can jump off of walls. Touching a wall also gives the air jumps back.
+    pub wall_jumping: bool,
+    /// surfaces tilted less than this angle away from vertical count as walls
+    pub max_wall_angle: f32,
+    /// maximum fall speed while pushing into a wall
+    pub max_wall_slide_speed: f32,
+    /// upward speed at the start of a wall jump
+    pub wall_jump_up_impulse: f32,
+    /// speed away from the wall at the start of a wall jump
+    pub wall_jump_away_impulse: f32,
+    /// height above the center of the collider, at which ledges are grabbed
+    pub ledge_grab_height: f32,
+    /// distance in front of the collider, in which ledges can be grabbed
+    pub ledge_grab_reach: f32,
+    /// how far above [`Player::ledge_grab_height`] the top of a ledge can be
+    pub ledge_search_height: f32,
+    /// speed while moving sideways along a ledge
+    pub shimmy_speed: f32,
+    /// speed while pulling up onto a ledge
+    pub mantle_speed: f32,
+    /// speed while dashing
+    pub dash_speed: f32,
+    /// seconds a dash lasts
+    pub dash_duration: f32,
+    /// seconds after a dash ended, before the next one can start
+    pub dash_cooldown: f32,
+    /// number of dashes the player can do before touching the ground again
+    pub dash_charges: usize,
+    /// what happens when the player runs into something while dashing
+    pub dash_collision: DashCollision,
+    /// acceleration while swimming. The top speed is reached once the drag of the water balances it.
+    pub swim_acceleration: f32,
+    /// fraction of the player's height, that has to be below the water surface to start swimming
+    pub swim_depth: f32,
+    /// upward speed at the start of a jump out of the water
+    pub swim_jump_impulse: f32,
+    /// speed while climbing
+    pub climb_speed: f32,
+    /// speed away from the climbable surface when jumping off of it
+    pub climb_jump_impulse: f32,
+    /// seconds after leaving the ground, during which the player can still jump
+    pub coyote_time: f32,
+    /// seconds a jump input is remembered, so jumping right before landing still works
+    pub jump_buffer_time: f32,
     pub airborne_acceleration: f32,
+    /// friction coefficient while sliding down slopes that are too steep to stand on
+    pub slope_friction: f32,
+    /// radius of the player's capsule collider
+    pub radius: f32,
+    /// total height of the player's capsule collider while standing
+    pub height: f32,
+    /// total height of the player's capsule collider while crouching
+    pub crouch_height: f32,
+    pub crouch_max_speed: f32,
+}
+
+impl Player {
+    /// Gravitational acceleration, that makes jumps reach [`Player::max_jump_height`] after
+    /// [`Player::jump_time_to_apex`].
+    pub fn gravity(&self) -> f32 {
+        2.0 * self.max_jump_height / self.jump_time_to_apex.powi(2)
+    }
+
+    /// Upward speed at the start of a jump.
+    pub fn jump_impulse(&self) -> f32 {
+        2.0 * self.max_jump_height / self.jump_time_to_apex
+    }
+
+    /// Upward speed, that is left after releasing the jump input early. It is just enough to reach
+    /// [`Player::min_jump_height`] when the input is released right away.
+    pub fn min_jump_speed(&self) -> f32 {
+        (2.0 * self.gravity() * self.min_jump_height).sqrt()
+    }
+
+    /// Capsule collider with the given total height.
+    pub(super) fn collider(&self, height: f32) -> Collider {
+        Collider::capsule(self.radius, height - 2.0 * self.radius)
+    }
+
+    /// Capsule mesh matching [`Player::collider`].
+    pub(super) fn mesh(&self, height: f32) -> Capsule3d {
+        Capsule3d::new(self.radius, height - 2.0 * self.radius)
+    }
 }
 
 impl Default for Player {
     fn default() -> Self {
         Self {
-            gravity: 9.81,
             acceleration: 30.0,
             max_speed: 7.5,
             sprint_acceleration: 40.0,
             sprint_max_speed: 10.0,
             grounded_deceleration: 30.0,
-            jump_impulse: 10.0,
+            max_jump_height: 5.0,
+            min_jump_height: 1.5,
+            jump_time_to_apex: 1.0,
+            air_jumps: Vec::new(),
+            wall_jumping: true,
+            max_wall_angle: 20f32.to_radians(),
+            max_wall_slide_speed: 2.0,
+            wall_jump_up_impulse: 7.0,
+            wall_jump_away_impulse: 6.0,
+            ledge_grab_height: 0.4,
+            ledge_grab_reach: 0.3,
+            ledge_search_height: 0.5,
+            shimmy_speed: 1.5,
+            mantle_speed: 3.0,
+            dash_speed: 20.0,
+            dash_duration: 0.15,
+            dash_cooldown: 0.3,
+            dash_charges: 1,
+            dash_collision: DashCollision::Slide,
+            swim_acceleration: 10.0,
+            swim_depth: 0.5,
+            swim_jump_impulse: 6.0,
+            climb_speed: 2.5,
+            climb_jump_impulse: 6.0,
+            coyote_time: 0.1,
+            jump_buffer_time: 0.15,
             airborne_acceleration: 15.0,
+            slope_friction: 0.3,
+            radius: 0.3,
+            height: 1.9,
+            crouch_height: 1.1,
+            crouch_max_speed: 3.0,
         }
     }
 }
 
+/// A jump the player can do while airborne.
+#[derive(Debug, Clone, Copy)]
+pub struct AirJump {
+    /// height of the jump in metres
+    pub height: f32,
+    /// how much of the horizontal velocity is turned towards the input direction, from 0 to 1
+    pub redirect: f32,
+}
+
+impl AirJump {
+    /// Upward speed at the start of the jump, for the given gravitational acceleration.
+    pub fn impulse(&self, gravity: f32) -> f32 {
+        (2.0 * gravity * self.height).sqrt()
+    }
+}
+
+/// How a dash reacts to running into a collider, that isn't walkable ground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DashCollision {
+    /// the dash ends right away
+    Stop,
+    /// the dash continues along the surface
+    Slide,
+}
+
+/// Added to players while dashing.
+#[derive(Debug, Component)]
+pub struct Dashing {
+    pub direction: Dir3,
+    /// seconds left until the dash ends
+    pub remaining: f32,
+}
+
+/// Added to players while swimming in a [`WaterVolume`](crate::physics::WaterVolume).
+#[derive(Debug, Component)]
+pub struct Swimming;
+
+/// Added to players while climbing in a [`Climbable`](crate::physics::Climbable) volume.
+#[derive(Debug, Component)]
+pub struct Climbing {
+    /// the collider of the climbable volume
+    pub volume: Entity,
+    /// direction pointing away from the climbable surface
+    pub normal: Vec3,
+}
+
+/// Added to players hanging from a ledge.
+#[derive(Debug, Component)]
+pub struct LedgeGrab {
+    /// the collider of the ledge
+    pub ledge: Entity,
+    /// surface normal of the wall below the ledge, pointing towards the player
+    pub wall_normal: Vec3,
+    /// point on the wall below the ledge, at the height of [`Player::ledge_grab_height`]
+    pub wall_point: Vec3,
+    /// position the player is pulled up to while mantling
+    pub mantle_target: Option<Vec3>,
+}
+
+/// Marks players whose collider is shrunk to [`Player::crouch_height`].
+#[derive(Component)]
+pub struct Crouching;
+
+/// Elapsed [`Time`] in seconds of the last jump input and the last jump.
+#[derive(Component, Default)]
+pub(super) struct JumpState {
+    /// whether the jump input was pressed since the last update of the movement
+    pub requested: bool,
+    /// the jump input, that hasn't led to a jump yet
+    pub requested_at: Option<f32>,
+    pub jumped_at: Option<f32>,
+    /// whether the player is still rising from the last jump, which is cut short when the jump
+    /// input is released
+    pub rising: bool,
+    /// number of [`Player::air_jumps`] used since the player was last grounded
+    pub air_jumps_used: usize,
+    /// collider and normal of the wall the player last jumped off of. Jumping off of the same wall
+    /// again is only possible after landing.
+    pub last_wall: Option<(Entity, Vec3)>,
+}
+
+impl JumpState {
+    pub fn is_locked_out(&self, wall: &WallContact) -> bool {
+        self.last_wall.is_some_and(|(last_wall, last_normal)| {
+            last_wall == wall.wall && last_normal.dot(wall.normal) > 0.9
+        })
+    }
+}
+
+#[derive(Component, Default)]
+pub(super) struct DashState {
+    /// whether the dash input was pressed since the last update
+    pub requested: bool,
+    /// number of [`Player::dash_charges`] used since the player was last grounded
+    pub charges_used: usize,
+    /// elapsed [`Time`] in seconds at which the last dash ended
+    pub ended_at: Option<f32>,
+}
+
+/// Added to airborne players, while they push into a wall.
+#[derive(Debug, Component)]
+pub struct WallContact {
+    /// the collider of the wall
+    pub wall: Entity,
+    /// surface normal of the wall, pointing towards the player
+    pub normal: Vec3,
+}
+
 #[derive(Resource)]
 pub(super) struct PlayerModel(pub Handle<Scene>);
+
+/// Marks the child entity the [`PlayerModel`] is spawned on.
+#[derive(Component)]
+pub(super) struct PlayerModelInstance;
@@ -0,0 +1,176 @@
+//! Headless app for testing character bodies, without a window or renderer. Every update advances
+//! time by [`TIMESTEP`], so tests can step a fixed number of frames and assert on the outcome.
+
+use crate::physics::{
+    CharacterSchedule, CharacterSet, CollisionLayer, Grounded, KinematicCharacterBody,
+    PhysicsPlugin, Velocity, WaterVolume,
+};
+use avian3d::prelude::*;
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use std::time::Duration;
+
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+pub const GRAVITY: f32 = 9.81;
+// half of the total height of the test body's capsule
+pub const BODY_HALF_HEIGHT: f32 = 0.3 + 0.65;
+
+pub fn test_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        bevy::scene::ScenePlugin,
+        PhysicsPlugin::default().with_debug_visualization(false),
+    ));
+    app.init_asset::<Mesh>();
+    let timestep = Duration::from_secs_f32(TIMESTEP);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+    app.insert_resource(Time::<Fixed>::from_duration(timestep));
+    app
+}
+
+/// Pulls bodies that aren't [`Grounded`] down along their up direction, like the movement of the
+/// player does.
+pub fn add_gravity(app: &mut App) {
+    app.add_systems(
+        CharacterSchedule,
+        apply_gravity.in_set(CharacterSet::Movement),
+    );
+}
+
+fn apply_gravity(
+    mut bodies: Query<(&KinematicCharacterBody, &mut Velocity), Without<Grounded>>,
+    time: Res<Time>,
+) {
+    for (body, mut velocity) in &mut bodies {
+        velocity.0 -= body.up * GRAVITY * time.delta_secs();
+    }
+}
+
+pub fn run(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.update();
+    }
+}
+
+pub fn spawn_terrain(app: &mut App, size: Vec3, center: Vec3) -> Entity {
+    app.world_mut()
+        .spawn((
+            RigidBody::Static,
+            Collider::cuboid(size.x, size.y, size.z),
+            CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+            Transform::from_translation(center),
+        ))
+        .id()
+}
+
+/// Spawns a floor with its top at `y = 0`.
+pub fn spawn_floor(app: &mut App) -> Entity {
+    spawn_terrain(app, Vec3::new(40.0, 1.0, 40.0), Vec3::new(0.0, -0.5, 0.0))
+}
+
+/// Spawns a wall of the given size, with the center of its bottom face at `center`.
+pub fn spawn_wall(app: &mut App, size: Vec3, center: Vec3) -> Entity {
+    spawn_terrain(app, size, center + Vec3::Y * size.y * 0.5)
+}
+
+/// Spawns a floor with its top at `y = 0` and `count` steps of the given height and depth,
+/// starting at `x = start`.
+pub fn spawn_stairs(app: &mut App, start: f32, count: usize, height: f32, depth: f32) {
+    spawn_floor(app);
+    let end = start + count as f32 * depth + 5.0;
+    for i in 0..count {
+        let step_start = start + i as f32 * depth;
+        let step_height = (i + 1) as f32 * height;
+        spawn_terrain(
+            app,
+            Vec3::new(end - step_start, step_height, 4.0),
+            Vec3::new((step_start + end) * 0.5, step_height * 0.5, 0.0),
+        );
+    }
+}
+
+/// Spawns a slab through the origin, that rises towards positive x with the given slope angle in
+/// radians. Returns the slab and the position at which the test body rests on it above the origin.
+pub fn spawn_slope(app: &mut App, angle: f32) -> (Entity, Vec3) {
+    let rotation = Quat::from_rotation_z(angle);
+    let slope = app
+        .world_mut()
+        .spawn((
+            RigidBody::Static,
+            Collider::cuboid(20.0, 1.0, 20.0),
+            CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+            Transform::from_rotation(rotation),
+        ))
+        .id();
+    // the lower hemisphere of the capsule touches the slope, its center is half of the segment
+    // length above the hemisphere's center
+    let normal = rotation * Vec3::Y;
+    (slope, normal * (0.5 + 0.3 + 0.01) + Vec3::Y * 0.65)
+}
+
+/// Spawns a square heightfield of the given size centered at the origin, with `resolution` samples
+/// of `height(x, z)` along each side.
+pub fn spawn_heightfield(
+    app: &mut App,
+    size: f32,
+    resolution: usize,
+    height: impl Fn(f32, f32) -> f32,
+) -> Entity {
+    let coordinate = |i: usize| -size * 0.5 + i as f32 * size / (resolution - 1) as f32;
+    let heights = (0..resolution)
+        .map(|row| {
+            (0..resolution)
+                .map(|column| height(coordinate(column), coordinate(row)))
+                .collect()
+        })
+        .collect();
+    app.world_mut()
+        .spawn((
+            RigidBody::Static,
+            Collider::heightfield(heights, Vec3::new(size, 1.0, size)),
+            CollisionLayers::new(CollisionLayer::Terrain, LayerMask::ALL),
+            Transform::default(),
+        ))
+        .id()
+}
+
+/// Spawns a [`WaterVolume`] of the given size, with the center of its surface at `surface`.
+pub fn spawn_water(app: &mut App, size: Vec3, surface: Vec3) -> Entity {
+    app.world_mut()
+        .spawn((
+            WaterVolume::default(),
+            Collider::cuboid(size.x, size.y, size.z),
+            Transform::from_translation(surface - Vec3::Y * size.y * 0.5),
+        ))
+        .id()
+}
+
+pub fn spawn_body(app: &mut App, velocity: Vec3) -> Entity {
+    spawn_body_at(app, Vec3::Y * (BODY_HALF_HEIGHT + 0.01), velocity)
+}
+
+pub fn spawn_body_at(app: &mut App, translation: Vec3, velocity: Vec3) -> Entity {
+    app.world_mut()
+        .spawn((
+            KinematicCharacterBody::default(),
+            Collider::capsule(0.3, 1.3),
+            CollisionLayers::new(CollisionLayer::Player, LayerMask::ALL),
+            Transform::from_translation(translation),
+            Velocity(velocity),
+        ))
+        .id()
+}
+
+pub fn translation(app: &App, body: Entity) -> Vec3 {
+    app.world().get::<Transform>(body).unwrap().translation
+}
+
+pub fn velocity(app: &App, body: Entity) -> Vec3 {
+    app.world().get::<Velocity>(body).unwrap().0
+}
+
+pub fn is_grounded(app: &App, body: Entity) -> bool {
+    app.world().get::<Grounded>(body).is_some()
+}